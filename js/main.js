@@ -244,7 +244,7 @@ async function fillUntilOverflow(pageElement, parentElement) {
             } else if (isNonbreaking(top)) { // Finished!
                 parentElement.removeChild(top);
                 children.push(top);
-            } else if (top.tagName == "TEXT" || top.tagName == "PRE") { // Split text
+            } else if (top.tagName == "TEXT" || (top.tagName == "PRE" && top.children.length == 0)) { // Split text (highlighted code is split like other elements)
                 let text = top.textContent;
                 top.textContent = "";
 
@@ -28,9 +28,7 @@ fn main() {
 
     fs::create_dir_all("./build").expect("Failed to create build dir");
     fs::copy("./README.md", "./build/README.md").expect("Failed to copy readme");
-    copy_dir("./default", "./build/default").expect("Failed to copy default dir");
-    copy_dir("./fonts", "./build/fonts").expect("Failed to copy default dir");
-    copy_dir("./js", "./build/js").expect("Failed to copy js dir");
+    // default/, fonts/ and js/ are embedded in the executable
     copy_dir("./examples", "./build/examples").expect("Failed to copy js dir");
 
     // Try to move the executable file both on Windows and Linux and report error if both fails
@@ -1,3 +1,6 @@
+// The code base uses explicit `return`s everywhere
+#![allow(clippy::needless_return)]
+
 use std::fs;
 
 
@@ -45,18 +48,12 @@ fn main() {
     <body>
     ");
 
-    match file_title.captures(&text) {
-        Some(capt) => {
-            res.push_str(&format!("<h1>{}</h1>", capt.name("title").unwrap().as_str()));
-        },
-        None => {},
+    if let Some(capt) = file_title.captures(&text) {
+        res.push_str(&format!("<h1>{}</h1>", capt.name("title").unwrap().as_str()));
     }
 
-    match file_desc.captures(&text) {
-        Some(capt) => {
-            res.push_str(capt.name("desc").unwrap().as_str());
-        },
-        None => {},
+    if let Some(capt) = file_desc.captures(&text) {
+        res.push_str(capt.name("desc").unwrap().as_str());
     }
 
     res.push_str("<h2>Tags</h2>");
@@ -114,10 +111,7 @@ fn parse_tag(capture: regex::Captures, math: bool) -> String {
         Some(attr) => {
             let alias = match alias_match.captures_iter(attr.as_str()).next() {
                 None => None,
-                Some(c) => match c.name("alias") {
-                    None => None,
-                    Some(val) => Some(val.as_str())
-                }
+                Some(c) => c.name("alias").map(|val| val.as_str())
             };
 
             let infix_alias = attr.as_str().contains("infix-alias");
@@ -175,7 +169,7 @@ fn parse_tag(capture: regex::Captures, math: bool) -> String {
 } 
 
 fn show_help() {
-    println!("");
+    println!();
     println!("Help:");
     println!("Automatically crates a documentation file from comments. See default/default.cowx to see how to use the comments.");
     println!("Usage: cargo run --bin doc-generator -- [COWX FILE] [OUTPUT FILE]");
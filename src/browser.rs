@@ -1,28 +1,77 @@
 
-use std::path::PathBuf; 
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::{fs, thread};
+use headless_chrome::Browser;
 use crate::Args;
+use crate::config::BrowserConfig;
 use crate::doc_options::DocOptions;
 use crate::log;
 
 // Sends the file to the browser!
 
-pub fn render_to_pdf(path: PathBuf, args: &Args, options: &DocOptions) -> Result<(), ()> {
-    // create the browser
+/// Opens the browser that will be used to create PDFs
+pub fn open_browser(args: &Args, config: &BrowserConfig) -> Result<Browser, ()> {
     log::log("Opening the browser...");
-    let browser = log::log_if_err(headless_chrome::Browser::new(
+    return log::log_if_err(Browser::new(
         headless_chrome::LaunchOptions { 
-            headless: !args.headful,
+            headless: !args.headful && !config.headful,
+            path: config.path.as_ref().map(PathBuf::from),
             ..Default::default()
-        }), "Couldn't find chromium on your computer. In order to create PDFs, a chromium must be installed.")?;
+        }), "Couldn't find chromium on your computer. In order to create PDFs, a chromium must be installed.");
+}
+
+
+/// Creates the PDF of the HTML file at `path`, and writes it at `pdf_path`
+/// 
+/// # Arguments
+/// * `browser`: the browser to use. If `None`, a browser is opened and stored here so that next calls can reuse it
+/// 
+pub fn render_to_pdf(path: &Path, pdf_path: &Path, browser: &mut Option<Browser>, args: &Args, config: &BrowserConfig, options: &DocOptions) -> Result<(), ()> {
+    let pdf = print_to_pdf(path, browser, args, config, options)?;
 
-    let tab = log::log_if_err(browser.new_tab(), "Can't crate new tab")?;
+    if let Some(parent) = pdf_path.parent() {
+        let _ = fs::create_dir_all(parent); // If it fails, the error will be reported just below
+    }
+
+    match fs::write(pdf_path, pdf) {
+        Ok(()) => {},
+        Err(err) => {
+            log::error(&format!("Failed to write the PDF file at {}: {}", pdf_path.display(), err));
+            return Err(());
+        },
+    }
+
+    if args.keep_alive && !args.watch {
+        log::log("Keeping the browser alive forever, stop it manually");
+        loop {
+            thread::park();
+        }
+    }
+
+    return Ok(());
+}
+
+
+/// Same as `render_to_pdf`, but returns the content of the PDF file instead of writing it
+pub fn print_to_pdf(path: &Path, browser: &mut Option<Browser>, args: &Args, config: &BrowserConfig, options: &DocOptions) -> Result<Vec<u8>, ()> {
+    // Reuse the browser if it's already opened
+    let tab = match browser.as_ref().map(|b| b.new_tab()) {
+        Some(Ok(tab)) => tab,
+        _ => {
+            // The browser isn't opened yet, or it has been closed since last time: open a new one
+            *browser = None;
+            let new_browser = open_browser(args, config)?;
+            let tab = log::log_if_err(new_browser.new_tab(), "Can't crate new tab")?;
+            *browser = Some(new_browser);
+            tab
+        },
+    };
 
     log::log("Splitting pages...");
 
     // Navigate to the page
-    let res = tab.navigate_to(&format!("file:///{}", &path.clone().into_os_string().into_string().expect("")));
+    let res = tab.navigate_to(&format!("file:///{}", &path.to_str().expect("")));
     log::log_if_err(res, "Failed to navigate to document (1). Make sure chomium is allowed to read the file.")?;
     log::log_if_err(tab.wait_until_navigated(), "Failed to navigate to document (2).")?;
 
@@ -34,13 +83,11 @@ pub fn render_to_pdf(path: PathBuf, args: &Args, options: &DocOptions) -> Result
     loop {
         let try_errors_object = tab.find_element("#cowtchoox-error-reporter");
 
-        match try_errors_object {
-            Ok(obj) => {
-                errors_object = obj;
-                break;
-            },
-            Err(_) => {}, // Wait more
+        if let Ok(obj) = try_errors_object {
+            errors_object = obj;
+            break;
         }
+        // Otherwise, wait more
 
         thread::sleep(Duration::from_millis(200));
     }
@@ -62,36 +109,22 @@ pub fn render_to_pdf(path: PathBuf, args: &Args, options: &DocOptions) -> Result
         ..Default::default()
     })).unwrap();
 
-    let mut pdf_path = path.parent().unwrap().to_path_buf();
-    pdf_path.push("out.pdf");
-    fs::write(pdf_path, pdf).unwrap();
-
     // Collect errors, stored in an html element. (Yes, it's ridiculous, but evaluate() isn't working)
     let all_errors = errors_object.get_inner_text().unwrap();
     
-    if all_errors.len() > 0 {            
+    if !all_errors.is_empty() {            
         for message in all_errors.split('\0') {
             log::warning(&format!("The browser is complaining: {}", message));
         }
     }
 
-    // Cleanup
-
-    if args.keep_alive {
-        log::log("Keeping the browser alive forever, stop it manually");
-        loop {}
+    // Cleanup (keep the tab if the user wants to see it)
+    if !args.keep_alive || args.watch {
+        let res = tab.close(true);
+        log::log_if_err(res, "Failed to close browser tab.")?;
     }
 
-    let res = tab.close(true);
-    log::log_if_err(res, "Failed to close browser tab.")?;
-
-    // NOTE: some background thread is panicking just before exit, so I added that to hide the error message
-    //       it's not so bad because it's the last thing that is done.
-    std::panic::set_hook(Box::new(|_info| {
-        // Do nothing if got a panic!
-    }));
-
-    return Ok(());
+    return Ok(pdf);
 }
 
 
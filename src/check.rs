@@ -0,0 +1,116 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+use cowtchoox::{config, doc_options, log, parser, writer, Args, Context, Failure};
+
+// The check subcommand: makes sure documents compile, without writing any file or opening the browser
+
+
+/// Checks all files, and reports every problem found. Returns the first failure if at least one file has errors.
+pub fn check_files(files: &Vec<String>, args: &Args, resources_dir: &PathBuf) -> Result<(), Failure> {
+    let mut failed_count = 0;
+    let mut first_failure = None;
+    for file in files {
+        log::log(&format!("Checking {}...", file));
+
+        if let Err(failure) = check_file(file, args, resources_dir) {
+            failed_count += 1;
+            first_failure.get_or_insert(failure);
+        }
+    }
+
+    if let Some(failure) = first_failure {
+        log::error(&format!("{} of the {} checked files have errors.", failed_count, files.len()));
+        return Err(failure);
+    }
+    else {
+        log::log(&format!("Checked {} files, no errors found.", files.len()));
+        return Ok(());
+    }
+}
+
+
+/// Does everything the compilation does, except creating the HTML and the PDF
+fn check_file(file: &str, args: &Args, resources_dir: &PathBuf) -> Result<(), Failure> {
+    let main_file_path = crate::get_source_path(file);
+
+    // The files may be in different projects, so each one gets its own configuration and tags
+    // A document from stdin uses the configuration of the working dir
+    let config = config::find_config(&std::env::current_dir().expect("Failed to get working dir").join(file))?;
+    let custom_tags = crate::parse_cowx_files(args, resources_dir, &config, &mut Vec::new())?;
+
+    let content = match crate::read_source(file, &main_file_path) {
+        Ok(content) => content,
+        Err(err) => {
+            log::error(&format!("failed to read source file {}: {}", file, err));
+            return Err(Failure::MissingInput);
+        },
+    };
+
+    let mut context = Context {
+        args,
+        custom_tags,
+        ignore_aliases: false,
+        default_dir: resources_dir.clone(),
+        main_file_path,
+        dependencies: RefCell::new(Vec::new()),
+        pdf_output_path: PathBuf::new(),
+        html_output_path: PathBuf::new(),
+        config,
+        flags: HashMap::new(),
+        errors: RefCell::new(Vec::new()),
+        unknown_flags_reported: RefCell::new(HashSet::new()),
+    };
+
+    let mut document = match parser::parse_file(&context.main_file_path, &content.chars().collect(), &context) {
+        Ok(node) => node,
+        Err(errors) => {
+            parser::log_errors(&errors);
+            return Err(Failure::Parse);
+        },
+    };
+
+    let options = doc_options::get_file_options(&mut document, &context.config)?;
+    writer::get_finished_body(document, &mut context, &options)?;
+
+    return Ok(());
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cowtchoox::log::LogLevel;
+
+    #[test]
+    fn every_file_is_checked_and_nothing_is_written() {
+        let dir = std::env::temp_dir().join(format!("cowtchoox-check-test-{}", std::process::id()));
+        let files = [
+            ("good.cow", "<document><head></head><body>$a/b$</body></document>"),
+            ("tag.cow", "<document><head></head><body><!unknowntag/></body></document>"),
+            ("math.cow", "<document><head></head><body>$a <%bad/>$</body></document>"),
+        ];
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.join(name), content).unwrap();
+        }
+
+        let paths: Vec<String> = files.iter().map(|(name, _)| dir.join(name).display().to_string()).collect();
+        let args = crate::tests::get_args(&paths[0]);
+        let (res, diagnostics) = log::capture(|| check_files(&paths, &args, &PathBuf::from(env!("CARGO_MANIFEST_DIR"))));
+
+        let written_count = std::fs::read_dir(&dir).unwrap().count();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert_eq!(res, Err(Failure::Parse));
+        assert_eq!(written_count, files.len());
+
+        let error_files: Vec<PathBuf> = diagnostics.iter()
+            .filter(|d| d.level == LogLevel::Error)
+            .filter_map(|d| d.position.as_ref().map(|pos| (*pos.file_path).clone()))
+            .collect();
+        assert_eq!(error_files, vec![dir.join("tag.cow"), dir.join("math.cow")]);
+    }
+}
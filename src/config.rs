@@ -0,0 +1,147 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::doc_options::{DocumentPath, PathType};
+use crate::{log, Failure};
+
+// Handle the project configuration file (cowtchoox.toml), that provides default options for all documents of a folder
+
+
+/// Name of the configuration file
+pub const CONFIG_FILE_NAME: &str = "cowtchoox.toml";
+
+
+/// Content of a configuration file. All paths are relative to the folder of the file.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub cowx: Vec<String>, // Cowx files parsed before the document, like --cowx
+    pub output_dir: Option<String>, // Where to put the PDF and HTML files, if not specified on the command line
+    pub document: DocumentConfig,
+    pub browser: BrowserConfig,
+
+    #[serde(skip)]
+    pub dir: PathBuf, // The folder that contains the file
+    #[serde(skip)]
+    pub path: Option<PathBuf>, // None if no file was found
+}
+
+
+/// Default values for the tags of the head of the documents. The head of a document overrides them.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DocumentConfig {
+    pub title: Option<String>,
+    pub format: Option<String>,
+    pub orientation: Option<String>,
+    pub paper_width: Option<f32>,
+    pub paper_height: Option<f32>,
+    pub css: Vec<String>,
+    pub js: Vec<String>,
+    pub footer: Option<String>,
+    pub header: Option<String>,
+}
+
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BrowserConfig {
+    pub headful: bool, // Same as --headful
+    pub path: Option<String>, // Path to the chromium executable. Found automatically if not specified
+}
+
+
+/// Looks for a configuration file in the folder of the document, then in the parent folders.
+/// Returns the default configuration if there is none.
+pub fn find_config(document_path: &Path) -> Result<Config, Failure> {
+    for dir in document_path.ancestors().skip(1) {
+        let path = dir.join(CONFIG_FILE_NAME);
+
+        if path.is_file() {
+            return read_config(&path);
+        }
+    }
+
+    return Ok(Config::default());
+}
+
+
+/// Reads and parses a configuration file
+pub fn read_config(path: &Path) -> Result<Config, Failure> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            log::error(&format!("Failed to read the configuration file at {}: {}", path.display(), err));
+            return Err(Failure::MissingInput);
+        },
+    };
+
+    let mut config: Config = match toml::from_str(&content) {
+        Ok(config) => config,
+        Err(err) => {
+            log::error(&format!("Invalid configuration file at {}: {}", path.display(), err));
+            return Err(Failure::Parse);
+        },
+    };
+
+    config.dir = path.parent().expect("Uuh?").to_path_buf();
+    config.path = Some(path.to_path_buf());
+
+    return Ok(config);
+}
+
+
+impl Config {
+    /// Converts a path written in the file to a real path
+    pub fn get_full_path(&self, path: &str) -> PathBuf {
+        return self.dir.join(path);
+    }
+
+
+    /// Same as `get_full_path`, but for paths that will end up in `DocOptions`
+    pub fn get_document_path(&self, path: &str) -> DocumentPath {
+        return DocumentPath {
+            path: self.get_full_path(path).display().to_string(),
+            path_type: PathType::Absolute,
+        };
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    #[test]
+    fn nearest_configuration_is_used() {
+        let dir = write_files(&[
+            ("cowtchoox.toml", "output-dir = \"out\""),
+            ("a/b/cowtchoox.toml", "cowx = [\"tags.cowx\"]"),
+        ]);
+
+        let config = find_config(&dir.join("a/b/doc.cow")).expect("should be valid");
+        assert_eq!(config.path, Some(dir.join("a/b/cowtchoox.toml")));
+        assert_eq!(config.get_full_path(&config.cowx[0]), dir.join("a/b/tags.cowx"));
+        assert_eq!(config.output_dir, None); // Configurations aren't merged
+
+        let config = find_config(&dir.join("a/doc.cow")).expect("should be valid");
+        assert_eq!(config.output_dir.as_deref(), Some("out"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        let dir = write_files(&[("cowtchoox.toml", "[document]\ntitel = \"Report\"")]);
+
+        let (res, diagnostics) = log::capture(|| find_config(&dir.join("doc.cow")));
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(res.err(), Some(Failure::Parse));
+        assert!(diagnostics[0].message.contains("titel"));
+    }
+}
@@ -1,7 +1,8 @@
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::{log, parser::Node, Context};
+use crate::{log, parser::Node, Context, Failure};
+use crate::config::Config;
 
 
 // Handle document options
@@ -25,6 +26,7 @@ pub struct DocOptions {
     pub header_file: Option<DocumentPath>,
     pub is_slides: bool,
     pub slides_resource: Option<DocumentPath>,
+    pub flags: Vec<(String, bool)>, // Flags declared in the head, and whether they are set there
 }
 
 
@@ -41,41 +43,79 @@ pub enum PathType {
     RelativeToDefaultDir
 }
 
-/// Calls get_options_form_head on the head.
-pub fn get_file_options(document: &mut Node) -> Result<DocOptions, ()> {
+/// Calls get_options_form_head on the head, using the values of the configuration file by default.
+pub fn get_file_options(document: &mut Node, config: &Config) -> Result<DocOptions, Failure> {
     let head = match crate::writer::try_get_children_with_name(document, "head") {
         Ok(head) => head,
         Err(()) => {
             log::error("The document has no head.");
-            return Err(());
+            return Err(Failure::Parse);
         }
     };
-    let options = get_options_form_head(head);
+    let options = get_options_form_head(head, get_options_from_config(config));
 
     return Ok(options);
 }
 
-/// Takes the raw head node form the document, and extract the options
-/// 
-/// # Panics
-/// panics if you pass an other node than head
-/// 
-pub fn get_options_form_head(head: &Node) -> DocOptions {
-    if head.name != "head" {
-        panic!("You must pass the head!");
-    }
+
+/// Get the options specified in the configuration file, and default values for the others
+pub fn get_options_from_config(config: &Config) -> DocOptions {
+    let document = &config.document;
 
     let mut res = DocOptions { // Put default values here
         title: String::from("You forgot to specify the title!"),
         format: DocFormat { width: 210.0, height: 297.0 }, // Default to A4
-        css_files: Vec::new(), // No additional css files linked by default
-        js_files: Vec::new(), // No additional css files linked by default
+        css_files: document.css.iter().map(|path| config.get_document_path(path)).collect(),
+        js_files: document.js.iter().map(|path| config.get_document_path(path)).collect(),
         cowx_files: Vec::new(),
-        footer_file: None,
-        header_file: None,
+        footer_file: document.footer.as_ref().map(|path| config.get_document_path(path)),
+        header_file: document.header.as_ref().map(|path| config.get_document_path(path)),
         is_slides: false,
         slides_resource: None,
+        flags: Vec::new(),
     };
+
+    if let Some(title) = &document.title {
+        res.title = title.clone();
+    }
+
+    if let Some(format) = &document.format {
+        res.format = get_format_from_name(format.clone());
+    }
+
+    match document.orientation.as_deref() {
+        None | Some("portrait") => {},
+        Some("landscape") => {
+            res.format = DocFormat { width: res.format.height, height: res.format.width };
+        },
+        Some(str) => {
+            log::warning(&format!("Invalid value for orientation in configuration file: \"{}\"", str));
+        },
+    }
+
+    if let Some(width) = document.paper_width {
+        res.format.width = width;
+    }
+    if let Some(height) = document.paper_height {
+        res.format.height = height;
+    }
+
+    return res;
+}
+
+
+/// Takes the raw head node form the document, and extract the options
+/// 
+/// # Arguments
+/// * `res`: the default options, the ones in the head will replace them. Files specified in the head are added to the default ones
+/// 
+/// # Panics
+/// panics if you pass an other node than head
+/// 
+pub fn get_options_form_head(head: &Node, mut res: DocOptions) -> DocOptions {
+    if head.name != "head" {
+        panic!("You must pass the head!");
+    }
     
     for child in &head.children {
         let inner_text = crate::parser::get_node_content_as_str(child);
@@ -141,6 +181,10 @@ pub fn get_options_form_head(head: &Node) -> DocOptions {
             "cowx" => {
                 res.cowx_files.push(get_doc_path_from_tag(child, inner_text));
             },
+            "flag" => {
+                let is_set = crate::parser::get_attribute_value(child, "set").is_ok();
+                res.flags.push((inner_text.trim().to_string(), is_set));
+            },
             "footer" => {
                 res.footer_file = Some(get_doc_path_from_tag(child, inner_text));
             },
@@ -174,7 +218,7 @@ fn get_format_from_name(text: String) -> DocFormat {
     let format = text.to_lowercase();
 
     if format.len() == 2 {
-        let (dim_a, dim_b): (f32, f32) = match format.chars().nth(0) {
+        let (dim_a, dim_b): (f32, f32) = match format.chars().next() {
             Some('a') => (26.0, 37.0),
             Some('b') => (31.0, 44.0),
             Some('c') => (28.0, 40.0),
@@ -256,7 +300,7 @@ impl DocumentPath {
     pub fn get_full_path(&self, context: &Context) -> PathBuf {
         match self.path_type {
             PathType::RelativeToFile => {
-                return PathBuf::from(self.path.clone());
+                return get_document_dir(context).join(&self.path);
             },
             PathType::Absolute => {
                 return PathBuf::from(self.path.clone());
@@ -268,5 +312,79 @@ impl DocumentPath {
             },
         }
     }
+
+
+    /// The path to write in the HTML. Paths relative to the file are kept as they are, the browser finds them from the HTML file, that is next to the document
+    pub fn get_link_path(&self, context: &Context) -> PathBuf {
+        match self.path_type {
+            PathType::RelativeToFile => {
+                return PathBuf::from(&self.path);
+            },
+            _ => {
+                return self.get_full_path(context);
+            },
+        }
+    }
+
+
+    /// Same as `get_full_path`, but paths relative to the file can also be found in the cowx search path
+    pub fn get_cowx_path(&self, context: &Context) -> PathBuf {
+        match self.path_type {
+            PathType::RelativeToFile => {
+                return crate::resources::find_cowx_file(Path::new(&self.path), get_document_dir(context));
+            },
+            _ => {
+                return self.get_full_path(context);
+            },
+        }
+    }
+}
+
+
+/// The folder of the document, that the paths of its head are relative to. Documents read from stdin are in the working directory
+fn get_document_dir<'a>(context: &'a Context) -> &'a Path {
+    return context.main_file_path.parent().unwrap_or(Path::new(""));
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DocumentConfig;
+    use crate::test_util::{parse_snippet, with_context};
+
+    fn get_options(head: &str, config: &Config) -> DocOptions {
+        let head = with_context(|context| parse_snippet(head, context));
+        return get_options_form_head(&head, get_options_from_config(config));
+    }
+
+    #[test]
+    fn head_overrides_the_configuration() {
+        let config = Config {
+            document: DocumentConfig {
+                title: Some(String::from("Default")),
+                format: Some(String::from("A5")),
+                orientation: Some(String::from("landscape")),
+                css: vec![String::from("style.css")],
+                footer: Some(String::from("footer.html")),
+                ..DocumentConfig::default()
+            },
+            dir: PathBuf::from("/project"),
+            ..Config::default()
+        };
+
+        let options = get_options("<head><title>Report</title><css>mine.css</css></head>", &config);
+        assert_eq!(options.title, "Report");
+        let a5 = get_format_from_name(String::from("A5"));
+        assert_eq!((options.format.width, options.format.height), (a5.height, a5.width)); // Landscape A5 from the configuration
+        let css: Vec<&str> = options.css_files.iter().map(|path| path.path.as_str()).collect();
+        assert_eq!(css, vec!["/project/style.css", "mine.css"]); // The files of the head are added
+
+        let options = get_options("<head><format>A4</format><footer>other.html</footer></head>", &config);
+        assert_eq!(options.title, "Default");
+        let a4 = get_format_from_name(String::from("A4"));
+        assert_eq!((options.format.width, options.format.height), (a4.width, a4.height)); // The orientation of the configuration isn't used either
+        assert_eq!(options.footer_file.map(|path| path.path), Some(String::from("other.html")));
+    }
+}
@@ -0,0 +1,92 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+
+use cowtchoox::{config, log, parser, Args, Context, Failure};
+
+// The fmt subcommand: rewrites cow and cowx files with consistent indentation and attribute layout
+
+
+/// Formats all files. With `check`, no file is written, and it fails if one of them is not formatted
+pub fn format_files(files: &Vec<String>, check: bool, args: &Args, resources_dir: &PathBuf) -> Result<(), Failure> {
+    let mut unformatted_count = 0;
+    let mut first_failure = None;
+    for file in files {
+        match format_file(file, check, args, resources_dir) {
+            Ok(true) => {},
+            Ok(false) => unformatted_count += 1,
+            Err(failure) => { first_failure.get_or_insert(failure); },
+        }
+    }
+
+    if let Some(failure) = first_failure {
+        return Err(failure);
+    }
+
+    if check && unformatted_count > 0 {
+        log::error(&format!("{} of the {} checked files are not formatted. Run \"cowtchoox fmt\" on them to format them.", unformatted_count, files.len()));
+        return Err(Failure::Unformatted);
+    }
+    else if check {
+        log::log(&format!("Checked {} files, all of them are formatted.", files.len()));
+    }
+
+    return Ok(());
+}
+
+
+/// Formats one file
+///
+/// # Returns
+/// * Whether the file was already formatted
+///
+fn format_file(file: &str, check: bool, args: &Args, resources_dir: &PathBuf) -> Result<bool, Failure> {
+    let path = crate::get_source_path(file);
+
+    let content = match fs::read_to_string(file) {
+        Ok(content) => content,
+        Err(err) => {
+            log::error(&format!("failed to read source file {}: {}", file, err));
+            return Err(Failure::MissingInput);
+        },
+    };
+
+    // Only the parser is used, so there are no custom tags
+    let context = Context {
+        args,
+        custom_tags: HashMap::new(),
+        ignore_aliases: false,
+        default_dir: resources_dir.clone(),
+        main_file_path: path.clone(),
+        dependencies: RefCell::new(Vec::new()),
+        pdf_output_path: PathBuf::new(),
+        html_output_path: PathBuf::new(),
+        config: config::Config::default(),
+        flags: HashMap::new(),
+        errors: RefCell::new(Vec::new()),
+        unknown_flags_reported: RefCell::new(HashSet::new()),
+    };
+
+    let formatted = match parser::format::format_source(&path, &content.chars().collect(), &context) {
+        Ok(formatted) => formatted,
+        Err(errors) => {
+            parser::log_errors(&errors);
+            return Err(Failure::Parse);
+        },
+    };
+
+    if formatted == content {
+        return Ok(true);
+    }
+
+    if check {
+        log::error(&format!("{} is not formatted.", file));
+    }
+    else {
+        log::log_if_err(fs::write(file, formatted), &format!("Failed to write {}.", file)).map_err(|_| Failure::Output)?;
+        log::log(&format!("Formatted {}.", file));
+    }
+
+    return Ok(false);
+}
@@ -0,0 +1,420 @@
+use crate::parser::Node;
+
+// Highlights code blocks when the HTML is written, so that documents don't need a script to do it
+// The code is cut into tokens (keywords, strings, comments...) that are put in spans with the class "hl-<kind>", styled by default/highlight.css
+
+
+/// What the highlighter needs to know about a language
+struct Language {
+    names: &'static [&'static str], // Names that can be used after the backticks, lowercase
+    keywords: &'static [&'static str],
+    literals: &'static [&'static str], // Like true, false, null
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    string_quotes: &'static [char],
+    char_quotes: bool, // Are single quotes used for characters (like 'a'), instead of strings?
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenKind {
+    Plain, Keyword, Literal, String, Number, Comment, Function
+}
+
+
+/// Languages that are recognized, but not highlighted
+const PLAIN_TEXT_NAMES: [&str; 3] = ["text", "plain", "txt"];
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern", "float", "for", "goto", "if",
+    "inline", "int", "long", "register", "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned",
+    "void", "volatile", "while", "bool", "#include", "#define", "#ifdef", "#ifndef", "#endif", "#if", "#else", "#pragma",
+    // C++
+    "class", "namespace", "template", "typename", "public", "private", "protected", "virtual", "override", "new", "delete", "this",
+    "using", "operator", "try", "catch", "throw", "constexpr", "friend", "explicit", "noexcept", "mutable",
+];
+
+const LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for", "if", "impl", "in",
+            "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        literals: &["true", "false", "None", "Some", "Ok", "Err"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"'],
+        char_quotes: true,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "cc", "hpp", "glsl", "hlsl"],
+        keywords: C_KEYWORDS,
+        literals: &["true", "false", "NULL", "nullptr"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"'],
+        char_quotes: true,
+    },
+    Language {
+        names: &["csharp", "cs", "c#"],
+        keywords: &[
+            "abstract", "as", "base", "bool", "break", "case", "catch", "char", "class", "const", "continue", "default", "do", "double",
+            "else", "enum", "float", "for", "foreach", "if", "in", "int", "interface", "internal", "is", "long", "namespace", "new",
+            "out", "override", "private", "protected", "public", "readonly", "ref", "return", "sealed", "static", "string", "struct",
+            "switch", "this", "throw", "try", "using", "var", "virtual", "void", "while", "async", "await", "get", "set",
+        ],
+        literals: &["true", "false", "null"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"'],
+        char_quotes: true,
+    },
+    Language {
+        names: &["java", "kotlin", "kt"],
+        keywords: &[
+            "abstract", "boolean", "break", "byte", "case", "catch", "char", "class", "continue", "default", "do", "double", "else",
+            "enum", "extends", "final", "finally", "float", "for", "if", "implements", "import", "instanceof", "int", "interface",
+            "long", "new", "package", "private", "protected", "public", "return", "short", "static", "super", "switch", "this",
+            "throw", "throws", "try", "void", "while", "var", "fun", "val", "when", "object",
+        ],
+        literals: &["true", "false", "null"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"'],
+        char_quotes: true,
+    },
+    Language {
+        names: &["javascript", "js", "typescript", "ts"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const", "continue", "default", "delete", "do", "else", "export",
+            "extends", "finally", "for", "function", "if", "import", "in", "instanceof", "let", "new", "of", "return", "static",
+            "super", "switch", "this", "throw", "try", "typeof", "var", "void", "while", "yield", "interface", "type", "enum",
+        ],
+        literals: &["true", "false", "null", "undefined", "NaN"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_quotes: &['"', '\'', '`'],
+        char_quotes: false,
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif", "else", "except", "finally",
+            "for", "from", "global", "if", "import", "in", "is", "lambda", "nonlocal", "not", "or", "pass", "raise", "return", "try",
+            "while", "with", "yield",
+        ],
+        literals: &["True", "False", "None"],
+        line_comments: &["#"],
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        char_quotes: false,
+    },
+    Language {
+        names: &["bash", "sh", "shell", "zsh"],
+        keywords: &[
+            "if", "then", "else", "elif", "fi", "for", "while", "until", "do", "done", "case", "esac", "in", "function", "return",
+            "local", "export", "echo", "cd", "exit",
+        ],
+        literals: &["true", "false"],
+        line_comments: &["#"],
+        block_comment: None,
+        string_quotes: &['"', '\''],
+        char_quotes: false,
+    },
+    Language {
+        names: &["json"],
+        keywords: &[],
+        literals: &["true", "false", "null"],
+        line_comments: &[],
+        block_comment: None,
+        string_quotes: &['"'],
+        char_quotes: false,
+    },
+];
+
+
+/// Returns the language of a code block, given with a class like "language-rust". Returns None if there is no such class
+pub fn get_language_name(node: &Node) -> Option<&str> {
+    let classes = node.attributes.iter().find(|attr| attr.name == "class")?.value.as_ref()?;
+    return classes.split_whitespace().find_map(|class| class.strip_prefix("language-"));
+}
+
+
+/// Is this language supported? Plain text is considered supported
+pub fn is_known_language(language_name: &str) -> bool {
+    return PLAIN_TEXT_NAMES.contains(&language_name.to_lowercase().as_str()) || find_language(language_name).is_some();
+}
+
+
+/// Returns the HTML of highlighted code. Text is put into <text> tags, and tokens into spans that can't be cut between two pages.
+/// Returns None if the language is unknown or is plain text
+///
+/// # Arguments
+/// * `code`: the code, not escaped
+/// * `language_name`: the name of the language, like "rust" (case insensitive)
+///
+pub fn get_highlighted_html(code: &str, language_name: &str) -> Option<String> {
+    let language = find_language(language_name)?;
+    let chars: Vec<char> = code.chars().collect();
+
+    let mut res = String::with_capacity(code.len() * 2);
+    let mut plain_text = String::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (kind, length) = read_token(&chars, i, language);
+
+        if kind == TokenKind::Plain {
+            plain_text.extend(&chars[i..i + length]);
+        }
+        else {
+            push_text(&mut res, &mut plain_text);
+
+            // Line breaks are kept outside the spans, so that the block can be cut between lines
+            let token: String = chars[i..i + length].iter().collect();
+            for (line_id, line) in token.split('\n').enumerate() {
+                if line_id > 0 {
+                    plain_text.push('\n');
+                    push_text(&mut res, &mut plain_text);
+                }
+
+                if !line.is_empty() {
+                    res.push_str(&format!("<span class=\"hl-{}\" nonbreaking>{}</span>", get_kind_class(kind), escape_html(line)));
+                }
+            }
+        }
+
+        i += length;
+    }
+
+    push_text(&mut res, &mut plain_text);
+
+    return Some(res);
+}
+
+
+fn find_language(language_name: &str) -> Option<&'static Language> {
+    let language_name = language_name.to_lowercase();
+    return LANGUAGES.iter().find(|language| language.names.contains(&language_name.as_str()));
+}
+
+
+/// Returns the kind and the length of the token that starts at `start`
+fn read_token(chars: &[char], start: usize, language: &Language) -> (TokenKind, usize) {
+    let first = chars[start];
+    let previous = if start > 0 { Some(chars[start - 1]) } else { None };
+
+    if let Some((open, close)) = language.block_comment {
+        if starts_with(chars, start, open) {
+            let end = find(chars, start + open.chars().count(), close).map_or(chars.len(), |end| end + close.chars().count());
+            return (TokenKind::Comment, end - start);
+        }
+    }
+
+    for comment in language.line_comments {
+        if starts_with(chars, start, comment) {
+            let end = find(chars, start, "\n").unwrap_or(chars.len());
+            return (TokenKind::Comment, end - start);
+        }
+    }
+
+    if language.string_quotes.contains(&first) {
+        return (TokenKind::String, get_string_length(chars, start));
+    }
+
+    if language.char_quotes && first == '\'' {
+        // Only a short literal, otherwise it may be a lifetime or a label
+        let mut end = start + 1;
+        if chars.get(end) == Some(&'\\') {
+            end += 1;
+        }
+
+        while end < chars.len() && end - start < 12 && chars[end] != '\'' && chars[end] != '\n' {
+            end += 1;
+        }
+
+        if chars.get(end) == Some(&'\'') && end > start + 1 {
+            return (TokenKind::String, end + 1 - start);
+        }
+        else {
+            return (TokenKind::Plain, 1);
+        }
+    }
+
+    let after_word = previous.is_some_and(is_word_char);
+
+    if first.is_ascii_digit() && !after_word {
+        let mut end = start + 1;
+        while end < chars.len() && (is_word_char(chars[end]) || (chars[end] == '.' && chars.get(end + 1).is_some_and(|c| c.is_ascii_digit()))) {
+            end += 1;
+        }
+
+        return (TokenKind::Number, end - start);
+    }
+
+    if (is_word_char(first) || first == '#') && !after_word {
+        let mut end = start + 1;
+        while end < chars.len() && is_word_char(chars[end]) {
+            end += 1;
+        }
+
+        let word: String = chars[start..end].iter().collect();
+
+        if language.keywords.contains(&word.as_str()) {
+            return (TokenKind::Keyword, end - start);
+        }
+        else if language.literals.contains(&word.as_str()) {
+            return (TokenKind::Literal, end - start);
+        }
+        else if first == '#' {
+            return (TokenKind::Plain, 1);
+        }
+        else if chars.get(end) == Some(&'(') || (chars.get(end) == Some(&'!') && matches!(chars.get(end + 1), Some('(') | Some('['))) { // Function or macro
+            return (TokenKind::Function, end - start);
+        }
+        else {
+            return (TokenKind::Plain, end - start);
+        }
+    }
+
+    return (TokenKind::Plain, 1);
+}
+
+
+/// Length of the string that starts at `start` with a quote, including the quotes. Backslashes escape the next character
+fn get_string_length(chars: &[char], start: usize) -> usize {
+    let quote = chars[start];
+
+    let mut end = start + 1;
+    while end < chars.len() {
+        if chars[end] == '\\' {
+            end += 2;
+        }
+        else if chars[end] == quote {
+            return end + 1 - start;
+        }
+        else {
+            end += 1;
+        }
+    }
+
+    return chars.len() - start;
+}
+
+
+fn is_word_char(c: char) -> bool {
+    return c.is_alphanumeric() || c == '_';
+}
+
+
+fn starts_with(chars: &[char], start: usize, text: &str) -> bool {
+    return text.chars().enumerate().all(|(i, c)| chars.get(start + i) == Some(&c));
+}
+
+
+/// Position of the first occurrence of `text` from `start`
+fn find(chars: &[char], start: usize, text: &str) -> Option<usize> {
+    return (start..chars.len()).find(|i| starts_with(chars, *i, text));
+}
+
+
+fn get_kind_class(kind: TokenKind) -> &'static str {
+    return match kind {
+        TokenKind::Plain => "plain",
+        TokenKind::Keyword => "keyword",
+        TokenKind::Literal => "literal",
+        TokenKind::String => "string",
+        TokenKind::Number => "number",
+        TokenKind::Comment => "comment",
+        TokenKind::Function => "function",
+    };
+}
+
+
+/// Adds the text in a <text> tag, and empties it
+fn push_text(res: &mut String, text: &mut String) {
+    if !text.is_empty() {
+        res.push_str(&format!("<text>{}</text>", escape_html(text)));
+        text.clear();
+    }
+}
+
+
+fn escape_html(text: &str) -> String {
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens of the code that are not plain text
+    fn get_tokens(code: &str, language_name: &str) -> Vec<(TokenKind, String)> {
+        let language = find_language(language_name).expect("should be known");
+        let chars: Vec<char> = code.chars().collect();
+        let mut res = Vec::new();
+
+        let mut i = 0;
+        while i < chars.len() {
+            let (kind, length) = read_token(&chars, i, language);
+            if kind != TokenKind::Plain {
+                res.push((kind, chars[i..i + length].iter().collect()));
+            }
+            i += length;
+        }
+
+        return res;
+    }
+
+    #[test]
+    fn keywords_literals_and_functions() {
+        let tokens = get_tokens("let letter = call(true) + 12;", "rust");
+
+        assert_eq!(tokens, vec![
+            (TokenKind::Keyword, String::from("let")),
+            (TokenKind::Function, String::from("call")),
+            (TokenKind::Literal, String::from("true")),
+            (TokenKind::Number, String::from("12")),
+        ]);
+    }
+
+    #[test]
+    fn strings_end_at_their_quote() {
+        let tokens = get_tokens("f(\"a \\\" b\", 'c', &'a x)", "rust");
+
+        // 'a is a lifetime, not a character
+        assert_eq!(tokens, vec![
+            (TokenKind::Function, String::from("f")),
+            (TokenKind::String, String::from("\"a \\\" b\"")),
+            (TokenKind::String, String::from("'c'")),
+        ]);
+    }
+
+    #[test]
+    fn comments_end_at_the_line_or_their_closing() {
+        let tokens = get_tokens("a // b \"c\"\nd /* e\nf */ g /* h", "c");
+
+        assert_eq!(tokens, vec![
+            (TokenKind::Comment, String::from("// b \"c\"")),
+            (TokenKind::Comment, String::from("/* e\nf */")),
+            (TokenKind::Comment, String::from("/* h")),
+        ]);
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        let html = get_highlighted_html("a < b && c // <x>\n", "rust").expect("should highlight");
+
+        assert_eq!(html, "<text>a &lt; b &amp;&amp; c </text><span class=\"hl-comment\" nonbreaking>// &lt;x&gt;</span><text>\n</text>");
+    }
+
+    #[test]
+    fn unknown_and_plain_languages_are_not_highlighted() {
+        assert!(get_highlighted_html("let a;", "cobol").is_none());
+        assert!(get_highlighted_html("let a;", "text").is_none());
+        assert!(is_known_language("Text") && is_known_language("RS") && !is_known_language("cobol"));
+    }
+}
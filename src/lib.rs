@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+// The code base uses explicit `return`s, `&Vec` arguments and `Result<_, ()>` everywhere
+#![allow(clippy::needless_return, clippy::ptr_arg, clippy::result_unit_err)]
+
+pub mod parser;
+pub mod writer;
+pub mod doc_options;
+pub mod browser;
+pub mod log;
+pub mod util;
+pub mod config;
+pub mod resources;
+pub mod highlight;
+
+#[cfg(test)]
+mod test_util;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parser::custom::{CustomTag, TagHash};
+
+pub use log::Diagnostic;
+
+// This file exposes cowtchoox as a library. The command line program (main.rs) is built on top of it
+
+
+pub struct Args {
+    pub headful: bool,
+    pub keep_alive: bool,
+    pub filepath: String,
+    pub no_pdf: bool,
+    pub cowx_files: Vec<String>,
+    pub watch: bool,
+    pub output: Option<String>, // Path of the PDF file, if specified by the user
+    pub html_output: Option<String>, // Path of the HTML file, if specified by the user
+    pub warnings_as_errors: bool,
+    pub resources_dir: Option<String>, // Folder that contains default/, js/ and fonts/, if specified by the user
+    pub emit_html: bool, // Print the HTML to stdout instead of creating files
+    pub variables: HashMap<String, String>, // Defined with -D, used with <:name/>
+    pub flags: Vec<String>, // Flags set with --flag, used with <!if flag="name">
+}
+
+
+/// Why a compilation failed. The program exits with a different code for each of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    Parse, // The document or a cowx file has errors
+    MissingInput, // A file could not be read
+    Browser, // The PDF could not be created
+    Output, // An output file could not be written
+    Warnings, // Warnings were reported, and the user asked to treat them as errors
+    Unformatted, // fmt --check found files that are not formatted
+}
+
+
+impl Failure {
+    /// The code the program should exit with
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::Parse => 1,
+            Failure::MissingInput => 2,
+            Failure::Browser => 3,
+            Failure::Warnings => 4,
+            Failure::Output => 5,
+            Failure::Unformatted => 6,
+        }
+    }
+}
+
+
+/// Contains useful information to parse a document
+pub struct Context<'a> {
+    pub args: &'a crate::Args, // Command line arguments
+    pub custom_tags: TagHash,
+    pub ignore_aliases: bool,
+    pub default_dir: PathBuf,
+    pub main_file_path: PathBuf,
+    pub dependencies: RefCell<Vec<PathBuf>>, // Every file read to compile the document, used by watch mode. In a RefCell, because the parser adds the included files
+    pub pdf_output_path: PathBuf,
+    pub html_output_path: PathBuf,
+    pub config: config::Config,
+    pub flags: HashMap<String, bool>, // Flags known by the document, and whether they are set. Filled when the head has been read
+    pub errors: RefCell<Vec<parser::ParseError>>, // Errors the parser recovered from, to report them all at once. See `parser::collect_errors`
+    pub unknown_flags_reported: RefCell<HashSet<(PathBuf, usize)>>, // Files and positions of the if blocks with an unknown flag that have been warned about, to warn only once per block
+}
+
+
+/// Options for `compile`
+pub struct CompileOptions {
+    pub base_dir: PathBuf, // Absolute path of the folder the other paths are relative to. The working directory is never used
+    pub file_path: PathBuf, // Path of the document. Used in diagnostics and to find relative paths, the file doesn't need to exist but its folder does
+    pub resources_dir: PathBuf, // The folder that contains "default", "js" and "fonts"
+    pub cowx_files: Vec<PathBuf>, // Additional cowx files, same as --cowx
+    pub create_pdf: bool,
+    pub variables: HashMap<String, String>, // Values of the variables used in the document, same as -D
+    pub flags: Vec<String>, // Flags to set, same as --flag
+}
+
+
+/// Everything produced by `compile`
+pub struct CompiledDocument {
+    pub html: String,
+    pub pdf: Option<Vec<u8>>, // Only if asked in the options
+    pub diagnostics: Vec<Diagnostic>, // Warnings reported during compilation
+}
+
+
+/// Compiles a document. Nothing is printed: warnings and errors are returned instead.
+/// 
+/// # Arguments
+/// * `source`: the content of the .cow file
+/// 
+/// # Returns
+/// * the document, or all warnings and errors if the compilation failed
+/// 
+pub fn compile(source: &str, options: &CompileOptions) -> Result<CompiledDocument, Vec<Diagnostic>> {
+    let (res, diagnostics) = log::capture(|| compile_inner(source, options));
+
+    match res {
+        Ok((html, pdf)) => return Ok(CompiledDocument { html, pdf, diagnostics }),
+        Err(_) => return Err(diagnostics),
+    }
+}
+
+
+// Does the job of `compile`, reporting errors through the log module
+fn compile_inner(source: &str, options: &CompileOptions) -> Result<(String, Option<Vec<u8>>), Failure> {
+    if !options.base_dir.is_absolute() {
+        log::error(&format!("The base folder of the options should be an absolute path, found {}.", options.base_dir.display()));
+        return Err(Failure::MissingInput);
+    }
+
+    let file_path = options.base_dir.join(&options.file_path);
+    let resources_dir = options.base_dir.join(&options.resources_dir);
+
+    let args = Args {
+        headful: false,
+        keep_alive: false,
+        filepath: file_path.display().to_string(),
+        no_pdf: !options.create_pdf,
+        cowx_files: Vec::new(),
+        watch: false,
+        output: None,
+        html_output: None,
+        warnings_as_errors: false,
+        resources_dir: None,
+        emit_html: false,
+        variables: options.variables.clone(),
+        flags: options.flags.clone(),
+    };
+
+    let config = config::find_config(&file_path)?;
+
+    let mut default_cowx_path = resources_dir.clone();
+    default_cowx_path.push("default");
+    default_cowx_path.push("default.cowx");
+
+    let mut custom_tags = parse_cowx_file(default_cowx_path.to_str().expect("Uuh?"), HashMap::new(), &args, true, &resources_dir)?;
+
+    let config_cowx_files = config.cowx.iter().map(|path| config.get_full_path(path));
+    let option_cowx_files = options.cowx_files.iter().map(|path| options.base_dir.join(path));
+    for cowx_file in config_cowx_files.chain(option_cowx_files) {
+        custom_tags = parse_cowx_file(cowx_file.to_str().expect("Uuh?"), custom_tags, &args, false, &resources_dir)?;
+    }
+
+    let mut context = Context {
+        args: &args,
+        custom_tags,
+        ignore_aliases: false,
+        default_dir: resources_dir.clone(),
+        main_file_path: file_path.clone(),
+        dependencies: RefCell::new(Vec::new()),
+        pdf_output_path: PathBuf::new(),
+        html_output_path: PathBuf::new(),
+        config,
+        flags: HashMap::new(),
+        errors: RefCell::new(Vec::new()),
+        unknown_flags_reported: RefCell::new(HashSet::new()),
+    };
+
+    let mut document = match parser::parse_file(&file_path, &source.chars().collect(), &context) {
+        Ok(node) => node,
+        Err(errors) => {
+            parser::log_errors(&errors);
+            return Err(Failure::Parse);
+        },
+    };
+
+    let doc_options = doc_options::get_file_options(&mut document, &context.config)?;
+    let html = writer::get_file_text(document, &mut context, &doc_options)?;
+
+    if !options.create_pdf {
+        return Ok((html, None));
+    }
+
+    // The browser can only open files, so write the HTML in a temporary file.
+    // It is next to the document, like the HTML file of the command line program, so that the relative paths in the HTML are the same
+    static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+    let html_path = file_path.with_file_name(format!(".cowtchoox-{}-{}.html", nanos, TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+    log::log_if_err(fs::write(&html_path, &html), "Failed to write the temporary HTML file.").map_err(|_| Failure::Browser)?;
+
+    let mut browser = None;
+    let pdf = browser::print_to_pdf(&html_path, &mut browser, &args, &context.config.browser, &doc_options);
+    let _ = fs::remove_file(&html_path);
+
+    return Ok((html, Some(pdf.map_err(|_| Failure::Browser)?)));
+}
+
+
+
+/// Parses a cowx file, and adds its tags to `custom_tags_hash`
+pub fn parse_cowx_file(file_name: &str, custom_tags_hash: HashMap<String, CustomTag>, arguments: &Args, is_default: bool, resources_dir: &PathBuf) -> Result<HashMap<String, CustomTag>, Failure> {
+    match std::fs::read_to_string(file_name) { // Try to read the file
+        Ok(content) => {
+            // Parse the file
+            let res_hash = parser::custom::parse_custom_tags(
+                &content.chars().collect::<Vec<char>>(), 
+                &mut parser::get_start_of_file_position(PathBuf::from(file_name)), 
+                custom_tags_hash, 
+                arguments,
+                is_default,
+                resources_dir,
+                &PathBuf::from(file_name)
+            );
+
+            match res_hash {
+                Ok(hash) => return Ok(hash),
+                Err(errors) => {
+                    parser::log_errors(&errors);
+                    return Err(Failure::Parse); // Fatal error, we're done!
+                }
+            }
+        },
+        Err(err) => {
+            log::error(&format!("Failed to read cowx file at {}: {}", file_name, err));
+            return Err(Failure::MissingInput);
+        }
+    } 
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Options to compile a file of the repository, relative to its root
+    fn get_options(file_path: &str) -> CompileOptions {
+        return CompileOptions {
+            base_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+            file_path: PathBuf::from(file_path),
+            resources_dir: PathBuf::from("."),
+            cowx_files: Vec::new(),
+            create_pdf: false,
+            variables: HashMap::new(),
+            flags: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn relative_paths_are_resolved_from_the_base_dir() {
+        // test.cow uses test.cowx, that is next to it
+        let source = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/test.cow")).unwrap();
+        let document = compile(&source, &get_options("test.cow")).expect("test.cow should compile");
+
+        assert!(document.html.contains("<title>Test document</title>"));
+    }
+
+    #[test]
+    fn relative_base_dir_is_refused() {
+        let mut options = get_options("test.cow");
+        options.base_dir = PathBuf::from(".");
+
+        let diagnostics = compile("<document><head></head><body></body></document>", &options).err().expect("should fail");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, log::LogLevel::Error);
+    }
+
+    #[test]
+    fn warnings_are_counted_by_each_compilation() {
+        let source = "<document><head></head><body>a && b</body></document>";
+
+        for _ in 0..2 {
+            let (res, outside_count) = log::count_warnings(|| compile(source, &get_options("doc.cow")));
+            let document = res.expect("should compile");
+
+            assert_eq!(document.diagnostics.iter().filter(|d| d.level == log::LogLevel::Warning).count(), 1);
+            assert_eq!(outside_count, 0);
+        }
+    }
+
+    #[test]
+    fn errors_are_returned_with_their_position() {
+        let source = "<document><head></head><body>\n<p>x</q>\n</body></document>";
+        let diagnostics = compile(source, &get_options("doc.cow")).err().expect("should fail");
+
+        let error = diagnostics.iter().find(|d| d.level == log::LogLevel::Error).expect("should have an error");
+        let position = error.position.as_ref().expect("should have a position");
+        assert!(error.message.starts_with("Unexpected closing tag"));
+        assert_eq!(*position.file_path, PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("doc.cow"));
+        assert_eq!((position.line, position.line_character), (1, 4));
+    }
+
+    #[test]
+    fn each_failure_has_its_own_exit_code() {
+        let get_failure = |source: &str, options: &CompileOptions| log::capture(|| compile_inner(source, options)).0.expect_err("should fail");
+
+        let mut options = get_options("doc.cow");
+        assert_eq!(get_failure("<document><body><p></q></body></document>", &options), Failure::Parse);
+
+        options.cowx_files.push(PathBuf::from("missing.cowx"));
+        assert_eq!(get_failure("<document><body></body></document>", &options), Failure::MissingInput);
+
+        let failures = [Failure::Parse, Failure::MissingInput, Failure::Browser, Failure::Output, Failure::Warnings, Failure::Unformatted];
+        let codes: HashSet<u8> = failures.iter().map(|failure| failure.exit_code()).collect();
+        assert_eq!(codes.len(), failures.len());
+        assert!(!codes.contains(&0));
+    }
+}
@@ -1,103 +1,396 @@
+use std::cell::{Cell, RefCell};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Mutex;
 
 use crate::util::FilePosition;
 use colored::Colorize;
+use serde::Serialize;
 
 
 // This file will show errors in the terminal
+// All messages are sent to a sink, that decides how to show them. It can be changed at runtime with `set_sink`
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LogLevel {
     Log, Warning, Error
 }
 
 
+/// A warning or an error, as reported to the user
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub level: LogLevel,
+    pub message: String,
+    pub position: Option<FilePosition>, // None if the message is not related to a place in a file
+    pub length: usize,
+    pub notes: Vec<String>, // Additional information shown below the message
+}
+
+
+/// Receives all the messages of the program
+pub trait DiagnosticsSink {
+    fn report(&mut self, diagnostic: &Diagnostic);
+}
+
+
+/// Prints the messages as colored text, with the line they refer to
+pub struct ConsoleSink {
+    pub use_stderr: bool, // Print to stderr, so that stdout only contains the output of the program
+}
+
+
+/// Prints warnings and errors as JSON, one record per line. Logs are ignored
+pub struct JsonSink {
+    pub use_stderr: bool,
+}
+
+
+/// Stores warnings and errors. Logs are ignored
+struct CaptureSink {
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+}
+
+
+/// The record written by `JsonSink`. Lines and columns start at 1
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    level: &'a str,
+    message: &'a str,
+    file: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
+    length: usize,
+    notes: &'a Vec<String>,
+}
+
+
+/// Content of the files that are not on the disk (like stdin), so that their lines can be shown in messages
+static SOURCES: Mutex<Vec<(PathBuf, String)>> = Mutex::new(Vec::new());
+
+
+thread_local! {
+    /// Where the messages of this thread go
+    static SINK: RefCell<Box<dyn DiagnosticsSink>> = RefCell::new(Box::new(ConsoleSink { use_stderr: false }));
+
+    /// Number of warnings reported by this thread during the current `count_warnings` call
+    static WARNING_COUNT: Cell<usize> = const { Cell::new(0) };
+}
+
+
+/// Sends all the next messages of this thread to `sink`. Returns the previous one
+pub fn set_sink(sink: Box<dyn DiagnosticsSink>) -> Box<dyn DiagnosticsSink> {
+    return SINK.with(|current| current.replace(sink));
+}
+
+
+/// Calls `f`, and returns the warnings and errors it reported instead of printing them. Logs are ignored.
+/// The captured warnings are not counted by `count_warnings`
+pub fn capture<T>(f: impl FnOnce() -> T) -> (T, Vec<Diagnostic>) {
+    let diagnostics = Rc::new(RefCell::new(Vec::new()));
+    let previous = set_sink(Box::new(CaptureSink { diagnostics: diagnostics.clone() }));
+    let previous_count = WARNING_COUNT.get();
+    let res = f();
+    WARNING_COUNT.set(previous_count);
+    set_sink(previous);
+
+    return (res, diagnostics.take());
+}
+
+
+/// Remembers the content of a file, so that it is used to show lines in messages instead of reading the file. Replaces the previous content if any
+pub fn register_source(path: &Path, content: &str) {
+    let mut sources = SOURCES.lock().unwrap();
+    sources.retain(|(source_path, _)| source_path != path);
+    sources.push((path.to_path_buf(), String::from(content)));
+}
+
+
+/// Sends a message to the sink
+pub fn report(diagnostic: Diagnostic) {
+    if diagnostic.level == LogLevel::Warning {
+        WARNING_COUNT.set(WARNING_COUNT.get() + 1);
+    }
+
+    let res = SINK.try_with(|sink| {
+        match sink.try_borrow_mut() {
+            Ok(mut sink) => { sink.report(&diagnostic); true },
+            Err(_) => false, // The sink panicked while reporting an other message
+        }
+    });
+
+    if res != Ok(true) {
+        eprintln!("{}", diagnostic.message);
+    }
+}
+
+
+/// Calls `f`, and returns the number of warnings it reported
+pub fn count_warnings<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let previous_count = WARNING_COUNT.replace(0);
+    let res = f();
+    let count = WARNING_COUNT.replace(previous_count);
+
+    return (res, count);
+}
+
+
 /// Prints a log
 pub fn log(message: &str) {
-    println!("    {} {}", "log:".blue(), message);
+    report(Diagnostic { level: LogLevel::Log, message: String::from(message), position: None, length: 0, notes: Vec::new() });
 }
 
 
 /// Prints a log with a position
 pub fn log_position(message: &str, start_pos: &FilePosition, length: usize) {
-    println!("    {} {} {}", "log:".blue(), message, display_path(start_pos).bright_black());
-    print_line(start_pos, length, LogLevel::Log);
+    report(Diagnostic { level: LogLevel::Log, message: String::from(message), position: Some(start_pos.clone()), length, notes: Vec::new() });
 }
 
 /// Prints a warning
 pub fn warning(message: &str) {
-    println!("{} {}", "warning:".yellow(), message);
+    warning_with_notes(message, Vec::new());
+}
+
+
+/// Prints a warning, followed by additional information
+pub fn warning_with_notes(message: &str, notes: Vec<String>) {
+    report(Diagnostic { level: LogLevel::Warning, message: String::from(message), position: None, length: 0, notes });
 }
 
 
 /// Prints a warning
 pub fn warning_position(message: &str, start_pos: &FilePosition, length: usize) {
-    println!("{} {} {}", "warning:".yellow(), message, display_path(start_pos).bright_black());
-    print_line(start_pos, length, LogLevel::Warning);
+    report(Diagnostic { level: LogLevel::Warning, message: String::from(message), position: Some(start_pos.clone()), length, notes: Vec::new() });
 }
 
 
 /// Prints an error
 pub fn error(message: &str) {
-    println!("  {} {}", "error:".red(), message);
+    error_with_notes(message, Vec::new());
+}
+
+
+/// Prints an error, followed by additional information
+pub fn error_with_notes(message: &str, notes: Vec<String>) {
+    report(Diagnostic { level: LogLevel::Error, message: String::from(message), position: None, length: 0, notes });
 }
 
 
 /// Prints an error
 pub fn error_position(message: &str, start_pos: &FilePosition, length: usize) {
-    println!("  {} {} {}", "error:".red(), message, display_path(start_pos).bright_black());
-    print_line(start_pos, length, LogLevel::Error);
+    report(Diagnostic { level: LogLevel::Error, message: String::from(message), position: Some(start_pos.clone()), length, notes: Vec::new() });
 }
 
 
 /// Displays the message if an error is received
 pub fn log_if_err<T, E>(res: Result<T, E>, message: &str) -> Result<T, ()> {
-    return res.or_else(|_| { error(message); Err(()) });
+    return res.map_err(|_| error(message));
 }
 
 
 /// Overrides the default panic message
 pub fn override_panic_message() {
     std::panic::set_hook(Box::new(|info| {
-        println!("  {} This error is a bug of cowtchoox, and you shouldn't see it. Please report this bug. Here is the error message:\n{}", "panic:".purple(), info);
+        error(&format!("panic: This error is a bug of cowtchoox, and you shouldn't see it. Please report this bug. Here is the error message:\n{}", info));
     }));
 }
 
 
-fn display_path(start_pos: &FilePosition) -> String {
-    return format!("at {}:{}:{}", start_pos.file_path.to_str().unwrap(), start_pos.line + 1, start_pos.line_character);
+impl DiagnosticsSink for ConsoleSink {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        let label = match diagnostic.level {
+            LogLevel::Log => format!("    {}", "log:".blue()),
+            LogLevel::Warning => format!("{}", "warning:".yellow()),
+            LogLevel::Error => format!("  {}", "error:".red()),
+        };
+
+        match &diagnostic.position {
+            Some(position) => {
+                self.print(&format!("{} {} {}", label, diagnostic.message, display_path(position).bright_black()));
+                self.print_line(position, diagnostic.length, diagnostic.level);
+            },
+            None => {
+                self.print(&format!("{} {}", label, diagnostic.message));
+            },
+        }
+
+        for note in &diagnostic.notes {
+            self.print(&format!("         {} {}", "note:".bright_black(), note));
+        }
+    }
 }
 
 
-fn print_line(start_pos: &FilePosition, length: usize, level: LogLevel) {
-    let content = fs::read_to_string((*start_pos.file_path).clone()).unwrap();
+impl ConsoleSink {
+    fn print(&self, text: &str) {
+        if self.use_stderr {
+            eprintln!("{}", text);
+        }
+        else {
+            println!("{}", text);
+        }
+    }
 
-    let line = match content.lines().nth(start_pos.line) {
-        Some(l) => l,
-        None => "",
-    };
 
-    let line_number_text = (start_pos.line + 1).to_string();
+    fn print_line(&self, start_pos: &FilePosition, length: usize, level: LogLevel) {
+        let line = match get_source_line(start_pos) {
+            Some(line) => line,
+            None => return, // The message has been printed, it's enough
+        };
+
+        let line_number_text = (start_pos.line + 1).to_string();
+
+        let spaces_vector = vec![b' '; line_number_text.len()];
+        let spaces = String::from_utf8_lossy(&spaces_vector); // Create a string of spaces with the same size as the line number
+
+        // Create underline text
+        let mut underline = String::with_capacity(line.len());
+        for _ in 0..start_pos.line_character {
+            underline.push(' ');
+        }
+        for _ in 0..length {
+            underline.push('~');
+        }
+
+        let colored_underline = match level {
+            LogLevel::Log => underline.blue(),
+            LogLevel::Warning => underline.yellow(),
+            LogLevel::Error => underline.red(),
+        };
+
+        self.print(&format!("         {} {}", spaces, "|".bright_black()));
+        self.print(&format!("         {} {} {}", line_number_text.bright_black(), "|".bright_black(), line));
+        self.print(&format!("         {} {} {}", spaces, "|".bright_black(), colored_underline));
+    }
+}
+
 
-    let spaces_vector = vec![b' '; line_number_text.len()];
-    let spaces = String::from_utf8_lossy(&spaces_vector); // Create a string of spaces with the same size as the line number
+impl DiagnosticsSink for JsonSink {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        let text = match get_json_record(diagnostic) {
+            Some(text) => text,
+            None => return,
+        };
 
-    // Create underline text
-    let mut underline = String::with_capacity(line.len());
-    for _ in 0..start_pos.line_character {
-        underline.push(' ');
+        if self.use_stderr {
+            eprintln!("{}", text);
+        }
+        else {
+            println!("{}", text);
+        }
     }
-    for _ in 0..length {
-        underline.push('~');
+}
+
+
+/// The line written by `JsonSink` for that diagnostic. None for logs
+fn get_json_record(diagnostic: &Diagnostic) -> Option<String> {
+    let level = match diagnostic.level {
+        LogLevel::Log => return None,
+        LogLevel::Warning => "warning",
+        LogLevel::Error => "error",
+    };
+
+    let record = JsonRecord {
+        level,
+        message: &diagnostic.message,
+        file: diagnostic.position.as_ref().map(|position| position.file_path.display().to_string()),
+        line: diagnostic.position.as_ref().map(|position| get_line_and_column(position).0),
+        column: diagnostic.position.as_ref().map(|position| get_line_and_column(position).1),
+        length: diagnostic.length,
+        notes: &diagnostic.notes,
+    };
+
+    return Some(serde_json::to_string(&record).expect("Uuh?"));
+}
+
+
+impl DiagnosticsSink for CaptureSink {
+    fn report(&mut self, diagnostic: &Diagnostic) {
+        if diagnostic.level != LogLevel::Log {
+            self.diagnostics.borrow_mut().push(diagnostic.clone());
+        }
     }
+}
+
 
-    let colored_underline = match level {
-        LogLevel::Log => underline.blue(),
-        LogLevel::Warning => underline.yellow(),
-        LogLevel::Error => underline.red(),
+/// The line of `pos`, from the registered sources or from the file. None if the file can't be read
+fn get_source_line(pos: &FilePosition) -> Option<String> {
+    let registered = SOURCES.lock().unwrap().iter().find(|(path, _)| *path == *pos.file_path).map(|(_, content)| content.clone());
+    let content = match registered {
+        Some(content) => content,
+        None => fs::read_to_string(&*pos.file_path).ok()?,
     };
 
-    println!("         {} {}", spaces, "|".bright_black());
-    println!("         {} {} {}", line_number_text.bright_black(), "|".bright_black(), line);
-    println!("         {} {} {}", spaces, "|".bright_black(), colored_underline);
+    return Some(String::from(content.lines().nth(pos.line).unwrap_or_default()));
+}
+
+
+fn display_path(start_pos: &FilePosition) -> String {
+    let (line, column) = get_line_and_column(start_pos);
+    return format!("at {}:{}:{}", start_pos.file_path.to_str().unwrap(), line, column);
+}
+
+
+/// The line and the column shown to the user, for all message formats. Both start at 1
+fn get_line_and_column(pos: &FilePosition) -> (usize, usize) {
+    return (pos.line + 1, pos.line_character + 1);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn columns_start_at_one() {
+        let pos = FilePosition { file_path: Rc::new(PathBuf::from("doc.cow")), absolute_position: 12, line: 2, line_character: 4 };
+
+        assert_eq!(get_line_and_column(&pos), (3, 5));
+        assert_eq!(display_path(&pos), "at doc.cow:3:5");
+    }
+
+    #[test]
+    fn lines_of_registered_sources_are_shown() {
+        let path = PathBuf::from("<registered test source>");
+        let pos = FilePosition { file_path: Rc::new(path.clone()), absolute_position: 4, line: 1, line_character: 0 };
+        assert_eq!(get_source_line(&pos), None);
+
+        register_source(&path, "abc\ndef\n");
+        assert_eq!(get_source_line(&pos).as_deref(), Some("def"));
+
+        register_source(&path, "abc\nghi\n");
+        assert_eq!(get_source_line(&pos).as_deref(), Some("ghi"));
+    }
+
+    #[test]
+    fn json_records_have_every_field() {
+        let pos = FilePosition { file_path: Rc::new(PathBuf::from("doc.cow")), absolute_position: 12, line: 2, line_character: 4 };
+        let diagnostic = Diagnostic { level: LogLevel::Error, message: String::from("Bad \"tag\""), position: Some(pos), length: 3, notes: vec![String::from("a note")] };
+
+        let record: serde_json::Value = serde_json::from_str(&get_json_record(&diagnostic).expect("should be written")).unwrap();
+        assert_eq!(record, serde_json::json!({
+            "level": "error", "message": "Bad \"tag\"", "file": "doc.cow", "line": 3, "column": 5, "length": 3, "notes": ["a note"],
+        }));
+
+        let diagnostic = Diagnostic { level: LogLevel::Warning, message: String::from("w"), position: None, length: 0, notes: Vec::new() };
+        let record: serde_json::Value = serde_json::from_str(&get_json_record(&diagnostic).expect("should be written")).unwrap();
+        assert_eq!(record["file"], serde_json::Value::Null);
+        assert_eq!(record["level"], "warning");
+
+        assert_eq!(get_json_record(&Diagnostic { level: LogLevel::Log, ..diagnostic }), None);
+    }
+
+    #[test]
+    fn messages_go_to_the_current_sink() {
+        let ((), outer) = capture(|| {
+            let ((), inner) = capture(|| warning("inner"));
+            assert_eq!(inner.len(), 1);
+            error("outer");
+        });
+
+        assert_eq!(outer.len(), 1);
+        assert_eq!(outer[0].message, "outer");
+    }
 }
@@ -1,44 +1,28 @@
-#![allow(dead_code)]
+// The code base uses explicit `return`s, `&Vec` arguments and `Result<_, ()>` everywhere
+#![allow(clippy::needless_return, clippy::ptr_arg, clippy::result_unit_err)]
 
-mod parser;
-mod writer;
-mod doc_options;
-mod browser;
-mod log;
-mod util;
+mod watch;
+mod check;
+mod fmt;
 
-use std::{collections::HashMap, fs, path::PathBuf};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, fs, io::Read, path::{Path, PathBuf}, process::ExitCode};
 
-use clap;
-use parser::custom::{CustomTag, TagHash};
+use cowtchoox::{browser, config, doc_options, log, parser, resources, writer, parse_cowx_file, Args, Context, Failure};
+use cowtchoox::config::Config;
+use cowtchoox::parser::custom::TagHash;
 
 // This file interprets command line arguments, and call the different modules's functions
 
-pub struct Args {
-    pub headful: bool,
-    pub keep_alive: bool,
-    pub filepath: String,
-    pub no_pdf: bool,
-}
-
-
-/// Contains useful information to parse a document
-pub struct Context<'a> {
-    pub args: &'a crate::Args, // Command line arguments
-    pub custom_tags: TagHash,
-    pub ignore_aliases: bool,
-    pub default_dir: PathBuf,
-    pub main_file_path: PathBuf,
-}
-
-fn main() -> Result<(), ()> {
+fn main() -> ExitCode {
     log::override_panic_message();
 
     let matches =
         clap::Command::new("cowtchoox") 
             .arg_required_else_help(true)
+            .args_conflicts_with_subcommands(true)
+            .subcommand_negates_reqs(true)
             .arg(
-                clap::arg!(<FILE> "Path to the file to compile")
+                clap::arg!(<FILE> "Path to the file to compile, or - to read it from stdin")
             )
             .arg(
                 clap::arg!(--headful "Actually opens the browser window")
@@ -50,96 +34,341 @@ fn main() -> Result<(), ()> {
                 clap::arg!(--"no-pdf" "Create no pdf file")
             )
             .arg(
-                clap::arg!(--cowx <FILE> "Includes a cowx file")
+                clap::arg!(--cowx <FILE> "Includes a cowx file. Can be used several times").global(true).action(clap::ArgAction::Append)
+            )
+            .arg(
+                clap::arg!(--watch "Recompiles the document each time it or one of its dependencies changes")
+            )
+            .arg(
+                clap::arg!(-o --output <FILE> "Path of the PDF file to create. Defaults to the name of the document with a .pdf extension")
+            )
+            .arg(
+                clap::arg!(--"warnings-as-errors" "Fails if any warning is reported").global(true)
+            )
+            .arg(
+                clap::arg!(--"resources-dir" <DIR> "Folder that contains the resources of cowtchoox (default/, js/ and fonts/). Found automatically if not specified").global(true)
+            )
+            .arg(
+                clap::arg!(--"html-output" <FILE> "Path of the HTML file to create. Defaults to the name of the document with a .html extension")
+            )
+            .arg(
+                clap::arg!(--emit <KIND> "What to produce: the HTML and PDF files, or the HTML printed to stdout")
+                    .value_parser(["files", "html"])
+                    .default_value("files")
+            )
+            .arg(
+                clap::arg!(-D --define <VARIABLE> "Defines a variable, that can be used in the document with <:NAME/>. Can be used several times")
+                    .value_name("NAME=VALUE")
+                    .value_parser(parse_variable)
+                    .action(clap::ArgAction::Append)
+                    .global(true)
+            )
+            .arg(
+                clap::arg!(--flag <NAME> "Sets a flag, to enable the content of <!if flag=\"NAME\"> blocks. Can be used several times")
+                    .action(clap::ArgAction::Append)
+                    .global(true)
+            )
+            .arg(
+                clap::arg!(--"message-format" <FORMAT> "How warnings and errors are printed: colored text, or one JSON record per line")
+                    .value_parser(["human", "json"])
+                    .default_value("human")
+                    .global(true)
+            )
+            .subcommand(
+                clap::Command::new("check")
+                    .about("Checks that documents compile, without creating any file or opening the browser")
+                    .arg(clap::arg!(<FILES>... "Paths to the files to check"))
+            )
+            .subcommand(
+                clap::Command::new("fmt")
+                    .about("Formats cow and cowx files: indents the content of the tags and puts the attributes of each tag on one line")
+                    .arg(clap::arg!(<FILES>... "Paths to the files to format"))
+                    .arg(clap::arg!(--check "Writes no file, but fails if one of them is not formatted"))
             )
             .get_matches();
 
     // Get the filepath from arguments
     let args = Args {
-        filepath: matches.get_one::<String>("FILE").unwrap().clone(),
+        filepath: matches.get_one::<String>("FILE").cloned().unwrap_or_default(), // Not provided with subcommands
         headful: *matches.get_one::<bool>("headful").unwrap(),
         keep_alive: *matches.get_one::<bool>("keepalive").unwrap(),
         no_pdf: *matches.get_one::<bool>("no-pdf").unwrap(),
+        cowx_files: matches.get_many::<String>("cowx").map(|files| files.cloned().collect()).unwrap_or_default(),
+        watch: *matches.get_one::<bool>("watch").unwrap(),
+        output: matches.get_one::<String>("output").cloned(),
+        html_output: matches.get_one::<String>("html-output").cloned(),
+        warnings_as_errors: *matches.get_one::<bool>("warnings-as-errors").unwrap(),
+        resources_dir: matches.get_one::<String>("resources-dir").cloned(),
+        emit_html: matches.get_one::<String>("emit").is_some_and(|kind| kind == "html"),
+        variables: matches.get_many::<(String, String)>("define").map(|variables| variables.cloned().collect()).unwrap_or_default(),
+        flags: matches.get_many::<String>("flag").map(|flags| flags.cloned().collect()).unwrap_or_default(),
     };
 
-    let mut custom_tags_hash = HashMap::new(); // Store tags in this
+    // Keep stdout for the HTML if it's printed there
+    if matches.get_one::<String>("message-format").is_some_and(|format| format == "json") {
+        log::set_sink(Box::new(log::JsonSink { use_stderr: args.emit_html }));
+    }
+    else {
+        log::set_sink(Box::new(log::ConsoleSink { use_stderr: args.emit_html }));
+    }
 
-    // HACK: assume cargo tests are always debug mode, and builds always release mode
-    #[allow(unused_mut)]
-    let mut exe_path;
+    let resources_dir = match resources::find_resources_dir(args.resources_dir.as_deref()) {
+        Ok(dir) => dir,
+        Err(failure) => return ExitCode::from(failure.exit_code()),
+    };
 
-    #[cfg(debug_assertions)] {
-        exe_path = std::env::current_dir().expect("Failed to get working dir");
+    if let Some(check_matches) = matches.subcommand_matches("check") {
+        let files: Vec<String> = check_matches.get_many::<String>("FILES").unwrap().cloned().collect();
+
+        let (res, warning_count) = log::count_warnings(|| check::check_files(&files, &args, &resources_dir));
+        return get_exit_code(res, warning_count, &args);
     }
 
-    #[cfg(not(debug_assertions))] {
-        let exe_path_owned = std::env::current_exe().expect("Cant' get executable location");
-        exe_path = exe_path_owned.parent().expect("Uuh?").to_owned();
+    if let Some(fmt_matches) = matches.subcommand_matches("fmt") {
+        let files: Vec<String> = fmt_matches.get_many::<String>("FILES").unwrap().cloned().collect();
+
+        let (res, warning_count) = log::count_warnings(|| fmt::format_files(&files, *fmt_matches.get_one::<bool>("check").unwrap(), &args, &resources_dir));
+        return get_exit_code(res, warning_count, &args);
     }
 
-    let mut default_dir_path = exe_path.clone();
-    default_dir_path.push("default");
-    default_dir_path.push("default.cowx");
+    if args.watch {
+        if args.filepath == STDIN_FILE_NAME {
+            log::error("Can't watch a document read from stdin.");
+            return ExitCode::from(Failure::MissingInput.exit_code());
+        }
 
-    log::log("Parsing cowx files...");
-    custom_tags_hash = parse_cowx_file(default_dir_path.to_str().expect("Uuh?"), custom_tags_hash, &args, true, &exe_path)?;
-
-    // Cowx file from command line
-    let cowx_file = matches.get_one::<String>("cowx");
-    match cowx_file {
-        Some(file_name) => {
-            match parse_cowx_file(file_name, custom_tags_hash, &args, false, &exe_path) {
-                Ok(hash) => custom_tags_hash = hash,
-                Err(_) => { return Ok(()); },
-            }
-        },
-        None => { },
+        watch::watch(&args, &resources_dir);
+    }
+
+    let mut browser = None;
+    let ((_, res), warning_count) = log::count_warnings(|| compile(&args, &resources_dir, &mut browser));
+
+    if browser.is_some() {
+        // NOTE: some background thread is panicking just before exit, so I added that to hide the error message
+        //       it's not so bad because it's the last thing that is done.
+        std::panic::set_hook(Box::new(|_info| {
+            // Do nothing if got a panic!
+        }));
+    }
+
+    return get_exit_code(res, warning_count, &args);
+}
+
+
+/// Parses the value of -D
+fn parse_variable(text: &str) -> Result<(String, String), String> {
+    let (name, value) = match text.split_once('=') {
+        Some(res) => res,
+        None => return Err(String::from("expected NAME=VALUE")),
+    };
+
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("invalid variable name \"{}\", use only letters, digits, _ and -", name));
+    }
+
+    return Ok((String::from(name), String::from(value)));
+}
+
+
+/// Converts the result of the program to an exit code. Also fails if warnings were reported and the user asked to treat them as errors.
+fn get_exit_code(res: Result<(), Failure>, warning_count: usize, args: &Args) -> ExitCode {
+    let res = res.and_then(|()| {
+        if args.warnings_as_errors && warning_count > 0 {
+            log::error(&format!("{} warnings reported, and they are treated as errors because of --warnings-as-errors.", warning_count));
+            return Err(Failure::Warnings);
+        }
+
+        return Ok(());
+    });
+
+    match res {
+        Ok(()) => return ExitCode::SUCCESS,
+        Err(failure) => return ExitCode::from(failure.exit_code()),
+    }
+}
+
+
+/// Parses the cowx files and compiles the document specified in the arguments.
+/// 
+/// # Arguments
+/// * `browser`: the browser used to create the PDF. Will be opened if needed, and left open for next compilations
+/// 
+/// # Returns
+/// * The paths of all files the compilation depends on, even if it failed
+/// * Whether the compilation succeeded
+/// 
+pub fn compile(args: &Args, resources_dir: &PathBuf, browser: &mut Option<headless_chrome::Browser>) -> (Vec<PathBuf>, Result<(), Failure>) {
+    let mut dependencies = Vec::new();
+    if args.filepath != STDIN_FILE_NAME {
+        dependencies.push(PathBuf::from(&args.filepath));
+    }
+
+    let path = get_source_path(&args.filepath);
+
+    // Where the document is considered to be, to find the configuration and name the outputs
+    let location = if args.filepath == STDIN_FILE_NAME {
+        std::env::current_dir().expect("Failed to get working dir").join("out")
     }
+    else {
+        path.clone()
+    };
+
+    let config = match config::find_config(&location) {
+        Ok(config) => config,
+        Err(failure) => return (dependencies, Err(failure)),
+    };
+
+    if let Some(config_path) = &config.path {
+        dependencies.push(config_path.clone());
+    }
+
+    let custom_tags_hash = match parse_cowx_files(args, resources_dir, &config, &mut dependencies) {
+        Ok(hash) => hash,
+        Err(failure) => return (dependencies, Err(failure)),
+    };
     
-    let res = std::fs::read_to_string(args.filepath.clone());
+    let res = read_source(&args.filepath, &path);
 
     match res {
         Ok(content) => {
-            let mut path = std::env::current_dir().expect("Failed to get working dir");
-            path.push(&args.filepath);
+            // Outputs given on the command line are relative to the working dir, like the source file
+            let pdf_output_path = match &args.output {
+                Some(output) => std::env::current_dir().expect("Failed to get working dir").join(output),
+                None => get_default_output_path(&location, &config, "pdf"),
+            };
+            let html_output_path = match &args.html_output {
+                Some(output) => std::env::current_dir().expect("Failed to get working dir").join(output),
+                None => get_default_output_path(&location, &config, "html"),
+            };
 
-            let context = Context {
-                args: &args,
+            let mut context = Context {
+                args,
                 custom_tags: custom_tags_hash,
                 ignore_aliases: false,
-                default_dir: exe_path,
+                default_dir: resources_dir.clone(),
                 main_file_path: path,
+                dependencies: RefCell::new(dependencies),
+                pdf_output_path,
+                html_output_path,
+                config,
+                flags: HashMap::new(),
+                errors: RefCell::new(Vec::new()),
+                unknown_flags_reported: RefCell::new(HashSet::new()),
             };
 
-            let res = compile_file(content, context);
+            let res = compile_file(content, &mut context, browser);
 
             match res {
                 Ok(_) => {},
+                Err(Failure::Browser) => {}, // The HTML file has been produced
                 Err(_) => {
                     log::log("No files produced.");
                 },
             }
+
+            return (context.dependencies.into_inner(), res);
         },
         Err(err) => {
             log::error(&format!("failed to read source file: {}", err));
+            return (dependencies, Err(Failure::MissingInput));
         },
     }
+}
 
-    return Ok(());
+
+/// The FILE argument that means the document is read from stdin
+pub const STDIN_FILE_NAME: &str = "-";
+
+
+/// Returns the path used for the document in messages. Documents from stdin get a virtual path
+pub fn get_source_path(file_name: &str) -> PathBuf {
+    if file_name == STDIN_FILE_NAME {
+        return PathBuf::from("<stdin>");
+    }
+    else {
+        return std::env::current_dir().expect("Failed to get working dir").join(file_name);
+    }
+}
+
+
+/// Reads the document from the file, or from stdin. In that case, the content is registered in the log module under `path`
+pub fn read_source(file_name: &str, path: &Path) -> std::io::Result<String> {
+    if file_name != STDIN_FILE_NAME {
+        return fs::read_to_string(file_name);
+    }
+
+    return read_registered_source(&mut std::io::stdin(), path);
+}
+
+
+/// Reads a document that is not a file, and registers its content in the log module under `path`
+fn read_registered_source(input: &mut impl Read, path: &Path) -> std::io::Result<String> {
+    let mut content = String::new();
+    input.read_to_string(&mut content)?;
+    log::register_source(path, &content);
+
+    return Ok(content);
+}
+
+
+/// Returns the path of an output file if none is specified on the command line: next to the document, or in the output dir of the configuration
+fn get_default_output_path(document_path: &PathBuf, config: &Config, extension: &str) -> PathBuf {
+    let path = document_path.with_extension(extension);
+
+    match &config.output_dir {
+        Some(output_dir) => return config.get_full_path(output_dir).join(path.file_name().expect("Uuh?")),
+        None => return path,
+    }
 }
 
 
-fn compile_file(content: String, mut context: Context) -> Result<(), ()> {
+/// Parses default.cowx, the cowx files of the configuration and the cowx files given in the arguments. Their paths are added to `dependencies`.
+pub fn parse_cowx_files(args: &Args, resources_dir: &PathBuf, config: &Config, dependencies: &mut Vec<PathBuf>) -> Result<TagHash, Failure> {
+    let mut default_dir_path = resources_dir.clone();
+    default_dir_path.push("default");
+    default_dir_path.push("default.cowx");
+
+    dependencies.push(default_dir_path.clone());
+
+    log::log("Parsing cowx files...");
+    let mut custom_tags_hash = parse_cowx_file(default_dir_path.to_str().expect("Uuh?"), HashMap::new(), args, true, resources_dir)?;
+
+    // Cowx files from the configuration
+    for file_name in &config.cowx {
+        let full_path = config.get_full_path(file_name);
+        dependencies.push(full_path.clone());
+        custom_tags_hash = parse_cowx_file(full_path.to_str().expect("Uuh?"), custom_tags_hash, args, false, resources_dir)?;
+    }
+
+    // Cowx files from command line
+    for file_name in &args.cowx_files {
+        let path = resources::find_cowx_file(Path::new(file_name), &std::env::current_dir().expect("Failed to get working dir"));
+        dependencies.push(path.clone());
+        custom_tags_hash = parse_cowx_file(path.to_str().expect("Uuh?"), custom_tags_hash, args, false, resources_dir)?;
+    }
+
+    return Ok(custom_tags_hash);
+}
+
+
+fn compile_file(content: String, context: &mut Context, browser: &mut Option<headless_chrome::Browser>) -> Result<(), Failure> {
     log::log("Parsing document...");
-    let mut document = match parser::parse_file(&context.main_file_path, &content.chars().collect(), &context) {
+    let mut document = match parser::parse_file(&context.main_file_path, &content.chars().collect(), context) {
         Ok(node) => node,
-        Err(err) => {
-            log::error_position(&err.message, &err.position, err.length);
-            return Err(());
+        Err(errors) => {
+            parser::log_errors(&errors);
+            return Err(Failure::Parse);
         },
     };
 
-    let options = doc_options::get_file_options(&mut document)?;
+    let options = doc_options::get_file_options(&mut document, &context.config)?;
+
+    // Remember the files listed in the head, so that watch mode can react to their changes
+    // The cowx files are added when they are loaded, as they can be found in the search path
+    for doc_path in options.css_files.iter().chain(&options.js_files).chain(&options.header_file).chain(&options.footer_file) {
+        let full_path = doc_path.get_full_path(context);
+        context.dependencies.borrow_mut().push(full_path);
+    }
 
     let cow_res_path = PathBuf::from("./cowtchoox_res");
     let original_default_dir = context.default_dir.clone();
@@ -148,97 +377,90 @@ fn compile_file(content: String, mut context: Context) -> Result<(), ()> {
     }
     
     log::log("Creating HTML...");
-    let text = match writer::get_file_text(document, &mut context, &options) {
-        Ok(res) => res,
-        Err(_) => {
-            return Err(());
-        },
-    };
+    let text = writer::get_file_text(document, context, &options)?;
 
-    // Remove filename form path and add
-    let mut out_path = context.main_file_path.parent().unwrap().to_path_buf();
+    if context.args.emit_html {
+        print!("{}", text);
+        log::log("Done!");
+        return Ok(());
+    }
 
     if options.is_slides {
-        out_path.push("out");
+        // Slides need their resources next to the HTML file, so put everything in a folder named after the document
+        if context.args.html_output.is_none() {
+            let file_name = context.html_output_path.file_name().expect("Uuh?").to_owned();
+            context.html_output_path = context.html_output_path.with_extension("");
+            context.html_output_path.push(file_name);
+        }
 
-        let _ = fs::remove_dir_all(out_path.clone()); // Try to remove the folder, it it exists 
+        let out_path = context.html_output_path.parent().expect("Uuh?").to_path_buf();
 
         let mut cowtchoox_res_path = out_path.clone();
         cowtchoox_res_path.push("cowtchoox_res");
-        match fs::create_dir_all(cowtchoox_res_path) {
+        let mut res_out_folder = out_path.clone();
+        res_out_folder.push("resources");
+
+        // Try to remove the folders created last time, if they exist
+        let _ = fs::remove_dir_all(cowtchoox_res_path.clone()); 
+        let _ = fs::remove_dir_all(res_out_folder.clone()); 
+
+        match fs::create_dir_all(out_path.clone()) {
             Ok(()) => (),
             Err(err) => {
                 log::error(&format!("Failed to create out folder. Make sure cowtchoox have necessary permissions. {:?}", &err));
-                return Err(());
+                return Err(Failure::Output);
             },
         }
 
         // Copy resource folder
         if let Some(ref res_folder) = options.slides_resource {
-            let mut res_out_folder = out_path.clone();
-            res_out_folder.push("resources");
-
-            match copy_dir::copy_dir(res_folder.get_full_path(&context), res_out_folder.clone()) {
+            match copy_dir::copy_dir(res_folder.get_full_path(context), res_out_folder.clone()) {
                 Ok(_) => (),
                 Err(err) => {
                     log::error(&format!(
                         "Failed to move your resource folder from {:?} to {:?}. Make sure cowtchoox have necessary permissions. {:?}", 
-                        res_folder.get_full_path(&context), 
+                        res_folder.get_full_path(context), 
                         res_out_folder, 
                         &err
                     ));
-                    return Err(());
+                    return Err(Failure::Output);
                 },
             }
         }
 
-        let mut cow_res_folder = out_path.clone();
-        cow_res_folder.push("cowtchoox_res");
-
-        let mut default_dist = cow_res_folder.clone();
-        default_dist.push("default");
-        let mut js_dist = cow_res_folder.clone();
-        js_dist.push("js");
-        let mut fonts_dist = cow_res_folder.clone();
-        fonts_dist.push("fonts");
-
-        let mut default_dir = original_default_dir.clone();
-        default_dir.push("default");
-        let mut default_js_dir = original_default_dir.clone();
-        default_js_dir.push("js");
-        let mut default_fonts_dir = original_default_dir.clone();
-        default_fonts_dir.push("fonts");
-
-        // Copy cowtchoox-res folder
-        let err = copy_dir::copy_dir(default_dir, &default_dist).and(
-            copy_dir::copy_dir(default_js_dir, &js_dist).and(
-                copy_dir::copy_dir(default_fonts_dir, &fonts_dist)
-            )
-        );
-
-        match err {
+        // Copy cowtchoox-res folder, from the resources used for documents
+        match resources::copy_resources(&original_default_dir, &cowtchoox_res_path) {
             Ok(_) => (),
             Err(err) => {
                 log::error(&format!("Failed to move internal resource folder. Make sure cowtchoox have necessary permissions. {:?}", &err));
-                return Err(());
+                return Err(Failure::Output);
             },
         }
     }
 
-    out_path.push("out.html");
+    if let Some(parent) = context.html_output_path.parent() {
+        let _ = fs::create_dir_all(parent); // If it fails, the error will be reported just below
+    }
 
-    fs::write(out_path.clone(), text).unwrap();
+    match fs::write(&context.html_output_path, text) {
+        Ok(()) => {},
+        Err(err) => {
+            log::error(&format!("Failed to write the HTML file at {}: {}", context.html_output_path.display(), err));
+            return Err(Failure::Output);
+        },
+    }
 
     // Render to pdf!
     if context.args.no_pdf {
         log::log("No PDF created because you used --no-pdf");
     }
     else {
-        let res = browser::render_to_pdf(out_path, context.args, &options);
+        let res = browser::render_to_pdf(&context.html_output_path, &context.pdf_output_path, browser, context.args, &context.config.browser, &options);
         match res {
             Ok(()) => {},
             Err(()) => {
-                log::log("Failed to create PDF file, but the HTML file have been created.")
+                log::log("Failed to create PDF file, but the HTML file have been created.");
+                return Err(Failure::Browser);
             },
         }
     }
@@ -248,32 +470,123 @@ fn compile_file(content: String, mut context: Context) -> Result<(), ()> {
 }
 
 
-pub fn parse_cowx_file(file_name: &str, custom_tags_hash: HashMap<String, CustomTag>, arguments: &Args, is_default: bool, exe_path: &PathBuf) -> Result<HashMap<String, CustomTag>, ()> {
-    match std::fs::read_to_string(file_name) { // Try to read the file
-        Ok(content) => {
-            // Parse the file
-            let res_hash = parser::custom::parse_custom_tags(
-                &content.chars().collect::<Vec<char>>(), 
-                &mut parser::get_start_of_file_position(PathBuf::from(file_name)), 
-                custom_tags_hash, 
-                &arguments,
-                is_default,
-                exe_path,
-                &PathBuf::from(file_name)
-            );
-
-            match res_hash {
-                Ok(hash) => return Ok(hash),
-                Err(err) => {
-                    log::error_position(&err.message, &err.position, err.length);
-                    return Err(()); // Fatal error, we're done!
-                }
-            }
-        },
-        Err(err) => {
-            log::error(&format!("Failed to read cowx file at {}: {}", file_name, err));
-            return Err(());
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments of a compilation of `filepath` with no options
+    pub fn get_args(filepath: &str) -> Args {
+        return Args {
+            headful: false,
+            keep_alive: false,
+            filepath: String::from(filepath),
+            no_pdf: true,
+            cowx_files: Vec::new(),
+            watch: false,
+            output: None,
+            html_output: None,
+            warnings_as_errors: false,
+            resources_dir: None,
+            emit_html: false,
+            variables: HashMap::new(),
+            flags: Vec::new(),
+        };
+    }
+
+    #[test]
+    fn outputs_are_named_after_the_document() {
+        let config = Config::default();
+        let document_path = PathBuf::from("/reports/report.cow");
+
+        assert_eq!(get_default_output_path(&document_path, &config, "pdf"), PathBuf::from("/reports/report.pdf"));
+        assert_eq!(get_default_output_path(&document_path, &config, "html"), PathBuf::from("/reports/report.html"));
+    }
+
+    #[test]
+    fn outputs_go_in_the_output_dir_of_the_configuration() {
+        let config = Config { output_dir: Some(String::from("build")), dir: PathBuf::from("/project"), ..Config::default() };
+        let document_path = PathBuf::from("/project/reports/report.cow");
+
+        assert_eq!(get_default_output_path(&document_path, &config, "pdf"), PathBuf::from("/project/build/report.pdf"));
+    }
+
+    #[test]
+    fn warnings_fail_only_with_warnings_as_errors() {
+        let mut args = get_args("doc.cow");
+        assert_eq!(get_exit_code(Ok(()), 2, &args), ExitCode::SUCCESS);
+        assert_eq!(get_exit_code(Err(Failure::Browser), 0, &args), ExitCode::from(3));
+
+        args.warnings_as_errors = true;
+        let (exit_code, diagnostics) = log::capture(|| get_exit_code(Ok(()), 2, &args));
+        assert_eq!(exit_code, ExitCode::from(Failure::Warnings.exit_code()));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(get_exit_code(Ok(()), 0, &args), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn missing_document_is_a_missing_input() {
+        let args = get_args("this-document-does-not-exist.cow");
+        let ((_, res), _) = log::capture(|| compile(&args, &PathBuf::from(env!("CARGO_MANIFEST_DIR")), &mut None));
+
+        assert_eq!(res, Err(Failure::MissingInput));
+    }
+
+    #[test]
+    fn every_cowx_argument_is_parsed() {
+        let dir = std::env::temp_dir().join(format!("cowtchoox-cowx-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.cowx"), "<!first-tag>a</first-tag>").unwrap();
+        fs::write(dir.join("b.cowx"), "<!second-tag>b</second-tag>").unwrap();
+
+        let mut args = get_args("doc.cow");
+        args.cowx_files = vec![dir.join("a.cowx").display().to_string(), dir.join("b.cowx").display().to_string()];
+        let mut dependencies = Vec::new();
+        let res = parse_cowx_files(&args, &PathBuf::from(env!("CARGO_MANIFEST_DIR")), &Config::default(), &mut dependencies);
+        let _ = fs::remove_dir_all(&dir);
+
+        let custom_tags = res.expect("should be valid");
+        assert!(custom_tags.contains_key("first-tag") && custom_tags.contains_key("second-tag"));
+        assert_eq!(dependencies[1..], [dir.join("a.cowx"), dir.join("b.cowx")]);
+    }
+
+    #[test]
+    fn documents_from_stdin_have_a_virtual_path() {
+        let path = get_source_path(STDIN_FILE_NAME);
+        let source = "<document><head></head><body>\n<p>x</q>\n</body></document>";
+        let content = read_registered_source(&mut source.as_bytes(), &path).expect("should be read");
+        assert_eq!(content, source);
+
+        let args = get_args(STDIN_FILE_NAME);
+        let context = Context {
+            args: &args,
+            custom_tags: HashMap::new(),
+            ignore_aliases: false,
+            default_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+            main_file_path: path.clone(),
+            dependencies: RefCell::new(Vec::new()),
+            pdf_output_path: PathBuf::new(),
+            html_output_path: PathBuf::new(),
+            config: Config::default(),
+            flags: HashMap::new(),
+            errors: RefCell::new(Vec::new()),
+            unknown_flags_reported: RefCell::new(HashSet::new()),
+        };
+
+        let errors = parser::parse_file(&path, &content.chars().collect(), &context).expect_err("should fail");
+        assert_eq!(*errors[0].position.file_path, PathBuf::from("<stdin>"));
+        assert_eq!(errors[0].position.line, 1);
+    }
+
+    #[test]
+    fn variables_are_name_value_pairs() {
+        assert_eq!(parse_variable("client=ACME"), Ok((String::from("client"), String::from("ACME"))));
+        assert_eq!(parse_variable("query=a=b"), Ok((String::from("query"), String::from("a=b"))));
+        assert_eq!(parse_variable("empty="), Ok((String::from("empty"), String::new())));
+
+        for text in ["client", "=ACME", "my client=ACME", "<a>=b"] {
+            assert!(parse_variable(text).is_err(), "{}", text);
         }
-    } 
+    }
 }
-
@@ -3,24 +3,23 @@ use std::vec::Vec;
 use std::path::PathBuf;
 
 use crate::Context;
+use crate::log;
 use crate::util::FilePosition;
 
 pub mod math;
 pub mod custom;
+pub mod conditions;
+pub mod include;
+pub mod format;
 
 // This file is parsing raw text into the Node struct
 
-// TODO: handle HTML comments
-
-// NOTE: currently auto-closing tags needs to include a / at the end (<br/>)
-//       should-it be mandatory?
-
 
 /// Chars to make a word (tag name, attribute, ...). Alphanumeric characters also included. 
 const WORD_CHARS: &str = "_-:"; 
 
 
-// Allowed autoclosing tags
+// Allowed autoclosing tags. All of them except path are void elements: they can't have content, so the slash is optional (<br> or <br/>)
 const AUTOCLOSING_TAGS: [&str; 17] = [
     "area", "base", "br", "col", "command", "embed", "hr", "img", "input", "keygen", "link", "meta", "param", "source", "track", "wbr", "path"
 ];
@@ -28,15 +27,17 @@ const AUTOCLOSING_TAGS: [&str; 17] = [
 
 #[derive(Debug, Clone)]
 pub enum NodeContent {
-    Character((char, FilePosition)), // Contains the char, then it's absolute position in the file
+    Text((String, FilePosition)), // A run of characters, then the position of the first one. A run is contiguous in the source file, see `get_run_offsets`
     EscapedCharacter((char, FilePosition)), // Character with backslash before it
+    Entity((String, FilePosition)), // An HTML character reference like "&nbsp;" or "&#169;", written as is
+    Ampersand((bool, FilePosition)), // "&", or "&&" if true. Replaced by `split_all_ampersands`
     Child(usize), // The positon of the child in the child array
 }
 
 
 /// This struct will own all of his children.
 /// The content field is a vect of NodeContent. 
-///     -> Each element is either a character or a child node. If it's a child, it indicates the position of the child in the children vector
+///     -> Each element is either a run of text, an escaped character, an entity, an ampersand or a child node. If it's a child, it indicates the position of the child in the children vector
 #[derive(Debug, Clone)]
 pub struct Node {
     pub name: String,
@@ -63,7 +64,7 @@ pub struct TagAttribute {
 
 
 /// Describes what went wrong
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParseError {
     pub message: String,
     pub position: FilePosition,
@@ -71,10 +72,10 @@ pub struct ParseError {
 }
 
 
-/// Used internally to determine if inside math node or code block
+/// Used internally to determine if inside math node or attribute value
 #[derive(PartialEq, Eq, Debug)]
 enum ParserState {
-    Normal, Math, BigMath, Code, BigCode, InsideAttributeValue
+    Normal, Math, BigMath, InsideAttributeValue
 }
 
 
@@ -97,21 +98,71 @@ struct SplitPosition {
 }
 
 
-/// Parses a raw file.
+/// Parses a raw file, and the files it includes.
 /// 
 /// # Arguments
 /// * `file`: the raw contents of the file
 /// 
 /// # Returns
-/// * the parsed node
+/// * the parsed node, or all the errors found in the file
 /// 
-pub fn parse_file(file_path: &PathBuf, chars: &Vec<char>, context: &Context) -> Result<Node, ParseError> {
-    let res = parse_tag(chars, &mut get_start_of_file_position(file_path.clone()), TagSymbol::NOTHING, false, context)?;
+pub fn parse_file(file_path: &PathBuf, chars: &Vec<char>, context: &Context) -> Result<Node, Vec<ParseError>> {
+    let res = parse_tag(chars, &mut get_start_of_file_position(file_path.clone()), TagSymbol::NOTHING, false, context);
+
+    // Insert included files, replace variables, and make sure it contains no other colon tag
+    let res = res.map(|mut node| {
+        include::resolve_includes(&mut node, &mut vec![include::get_include_path(file_path)], context);
+        custom::replace_variables(&mut node, &context.args.variables, &Vec::new());
+        custom::check_colon_tags(&node, &Vec::new(), context);
+        node
+    });
+
+    return collect_errors(res, context);
+}
 
-    // Make sure it contains no colon tag
-    custom::check_colon_tags(&res, &Vec::new())?;
 
-    return Ok(res);
+/// Remembers an error, so that parsing can continue and find the next ones. They are returned by `collect_errors`
+pub fn add_error(context: &Context, error: ParseError) {
+    let mut errors = context.errors.borrow_mut();
+
+    // The same error can be found again after recovering, sometimes with another message. The first one is the most precise
+    let already_found = errors.iter().any(|e| e.position.absolute_position == error.position.absolute_position && e.position.file_path == error.position.file_path);
+    if !already_found {
+        errors.push(error);
+    }
+}
+
+
+/// Returns the result of a parsing step if no error were found. Otherwise, returns the errors the parser recovered from and the error of the result, sorted by position.
+/// The errors are removed from the context
+pub fn collect_errors<T>(res: Result<T, ParseError>, context: &Context) -> Result<T, Vec<ParseError>> {
+    let res = res.map_err(|err| add_error(context, err));
+
+    let mut errors = context.errors.take();
+    if errors.is_empty() { // So the result is Ok
+        return Ok(res.expect("Uuh?"));
+    }
+
+    // Files are kept in the order of their first error
+    let mut files = Vec::new();
+    for err in &errors {
+        if !files.contains(&err.position.file_path) {
+            files.push(err.position.file_path.clone());
+        }
+    }
+    errors.sort_by_key(|err| (files.iter().position(|file| *file == err.position.file_path), err.position.absolute_position));
+
+    return Err(errors);
+}
+
+
+/// Prints the errors, then their number
+pub fn log_errors(errors: &Vec<ParseError>) {
+    for err in errors {
+        log::error_position(&err.message, &err.position, err.length);
+    }
+
+    log::error(&format!("Parsing failed with {} error{}.", errors.len(), if errors.len() > 1 { "s" } else { "" }));
 }
 
 
@@ -126,8 +177,18 @@ pub fn parse_file(file_path: &PathBuf, chars: &Vec<char>, context: &Context) ->
 /// # Returns
 /// * the parsed node
 /// 
-pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: TagSymbol, math: bool, context: &Context) -> Result<Node, ParseError> {
-    expect(chars, &mut pos, '<')?;
+pub fn parse_tag(chars: &Vec<char>, pos: &mut FilePosition, expect_symbol: TagSymbol, math: bool, context: &Context) -> Result<Node, ParseError> {
+    return parse_tag_with_parents(chars, pos, expect_symbol, math, &mut Vec::new(), context);
+}
+
+
+/// Same as `parse_tag`, but knows the tags that contain this one, to recover from unclosed tags
+///
+/// # Arguments
+/// * `open_tags`: names of the tags that contain this one, the closest last
+///
+fn parse_tag_with_parents(chars: &Vec<char>, pos: &mut FilePosition, expect_symbol: TagSymbol, math: bool, open_tags: &mut Vec<String>, context: &Context) -> Result<Node, ParseError> {
+    expect(chars, pos, '<')?;
 
     let start_pos = pos.clone();
 
@@ -161,19 +222,19 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
         }
     }
 
-    let mut attributes = Vec::with_capacity(10);
+    let mut attributes = Vec::new();
 
     // Read tag name
-    let tag_name = read_word(chars, &mut pos)?;
+    let tag_name = read_word(chars, pos)?;
 
     // Read the attributes
     loop {
         let attr_pos = pos.clone();
 
-        let attr = read_word(chars, &mut pos)?;
+        let attr = read_word(chars, pos)?;
 
         // No more attributes
-        if attr == "" {
+        if attr.is_empty() {
             break;
         }
         
@@ -197,6 +258,9 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
                     value = read_word(chars, pos)?;
                 }
 
+                // Values of custom tag arguments are parsed later, which will skip their comments
+                let value = if attr.starts_with(':') { value } else { remove_html_comments(&value) };
+
                 attributes.push(TagAttribute {
                     name: attr,
                     value: Some(value),
@@ -227,6 +291,10 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
 
     let is_really_math = math || used_symbol == TagSymbol::QUESTION_MARK || tag_name == "mathnode";
 
+    // HTML form of a void element, like <br>: it is auto-closing even without the slash
+    let is_html_tag = used_symbol == TagSymbol::NOTHING || used_symbol == TagSymbol::PERCENTAGE;
+    let got_autoclosing_slash = if is_html_tag && is_void_element(&tag_name) { Ok(()) } else { got_autoclosing_slash };
+
     match got_autoclosing_slash {
         Ok(()) => { // Auto-closing
 
@@ -289,9 +357,14 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
                 source_length: 0,
             };
 
-            parse_inner_tag(chars, &mut res, pos, if is_really_math { ParserState::Math } else { ParserState::Normal }, true, context)?;
-            expect(chars, &mut pos, '<')?;
-            expect(chars, &mut pos, '/')?;
+            open_tags.push(res.name.clone());
+            let inner_res = parse_inner_tag(chars, &mut res, pos, if is_really_math { ParserState::Math } else { ParserState::Normal }, true, open_tags, context);
+            open_tags.pop();
+            inner_res?;
+
+            let closing_tag_pos = pos.clone();
+            expect(chars, pos, '<')?;
+            expect(chars, pos, '/')?;
             
             // Parse the node's contents
             // Got out of the contents, now cursor is in closing tag
@@ -304,11 +377,16 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
                     String::new()
                 };
 
-                return Err(ParseError { 
+                // The closing tag belongs to a tag that contains this one (`parse_inner_tag` only stops at those): consider that this one ends here
+                add_error(context, ParseError { 
                     message: format!("Unmatched tag. Expected to close tag \"{}\", but found tag \"{}\".{}", res.name, closing_tag_name, math_hint), 
                     position: pos.clone(), 
                     length: closing_tag_name.len() 
                 });
+
+                *pos = closing_tag_pos;
+                res.source_length = get_positions_difference(pos, &res.start_position);
+                return Ok(res);
             }
             read_word(chars, pos)?; // Advance cursor to after the tag name 
 
@@ -325,60 +403,34 @@ pub fn parse_tag(chars: &Vec<char>, mut pos: &mut FilePosition, expect_symbol: T
 }
 
 
+/// Is it an HTML element that can't have content? These can be written without the slash (<br>), and can't have a closing tag
+fn is_void_element(tag_name: &str) -> bool {
+    return AUTOCLOSING_TAGS.contains(&tag_name) && tag_name != "path"; // path can contain animations in SVG
+}
+
+
 /// Parses text inside a tag. Helper for `parse_tag`
-fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosition, state: ParserState, allow_closing_tag: bool, context: &Context) -> Result<(), ParseError> {
-    let mut children: Vec<Node> = Vec::with_capacity(10);
-    let mut content: Vec<NodeContent> = Vec::with_capacity(100);
+/// Errors in the children are remembered with `add_error`, and parsing continues after them
+///
+/// # Arguments
+/// * `open_tags`: names of the tags that contain this text, the closest last
+///
+fn parse_inner_tag(chars: &Vec<char>, node: &mut Node, pos: &mut FilePosition, state: ParserState, allow_closing_tag: bool, open_tags: &mut Vec<String>, context: &Context) -> Result<(), ParseError> {
+    let mut children: Vec<Node> = Vec::new();
+    let mut content: Vec<NodeContent> = Vec::new();
+    let mut text_end = 0; // Where the last text run of the content ends in the source, see `push_character`
+
+    let mut failed_tags: Vec<String> = Vec::new(); // Names of the children that could not be parsed, their closing tags are ignored
     
     let mut backslashed_character = false; // Should the next character be ignored because of a backslash
 
-    let mut simple_splits: Vec<SplitPosition> = Vec::new();
-    let mut double_splits: Vec<SplitPosition> = Vec::new();
-
-    let start_pos = pos.clone();
-
     loop {
-        let next = chars[(*pos).absolute_position];
-
-        // Code: ignore all but backticks
-        if state == ParserState::Code || state == ParserState::BigCode {
-            if next == '`' {
-                advance_position_with_comments(pos, chars)?;
-            
-                let double = chars[pos.absolute_position] == '`';
-
-                if state == ParserState::Code {
-                    if double {
-                        return Err(ParseError {
-                            message: String::from("Expected a single backtick (and not two) to close code block."),
-                            position: pos.clone(),
-                            length: 1,
-                        })
-                    }
-                    else {
-                        break;
-                    }
-                }
-                else if state == ParserState::BigCode {
-                    if double {
-                        advance_position_with_comments(pos, chars)?;
-                        break;
-                    }
-                    else {
-                        //It's a regular character
-                        content.push(NodeContent::Character(('`', pos.clone())));
-                    }
-                }
-            }
-            else {
-                advance_position_with_comments(pos, chars)?;
-                content.push(NodeContent::Character((next, pos.clone())));
-            }
-
-            continue; // Nothing else!
+        // Comments can start right after a code block, or at the beginning of an attribute value
+        if !backslashed_character {
+            skip_comments(chars, pos)?;
         }
 
-        // If not code:
+        let next = chars[pos.absolute_position];
 
         if backslashed_character { // Escaped by backslash
             content.push(NodeContent::EscapedCharacter((next, pos.clone())));
@@ -399,6 +451,33 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
 
             match next_char {
                 '/' => { // Reached closing tag
+                    let close_tag_name = lookahead_word(chars, &mut lookahead_pos)?;
+                    if is_void_element(&close_tag_name) && close_tag_name != node.name {
+                        add_error(context, ParseError {
+                            message: format!("Unexpected closing tag \"</{}>\". \"{}\" is a void element: it can't have content, so it is never closed. Remove this closing tag.", close_tag_name, close_tag_name),
+                            position: pos.clone(),
+                            length: 3 + close_tag_name.chars().count(),
+                        });
+
+                        skip_closing_tag(chars, pos)?;
+                        continue;
+                    }
+
+                    // Closes nothing: ignore it
+                    if allow_closing_tag && close_tag_name != node.name && !open_tags.contains(&close_tag_name) {
+                        match failed_tags.iter().position(|name| *name == close_tag_name) {
+                            Some(id) => { failed_tags.remove(id); }, // The error was already reported for the opening tag
+                            None => add_error(context, ParseError {
+                                message: format!("Unexpected closing tag \"</{}>\". No \"{}\" tag is open here.", close_tag_name, close_tag_name),
+                                position: pos.clone(),
+                                length: 3 + close_tag_name.chars().count(),
+                            }),
+                        }
+
+                        skip_closing_tag(chars, pos)?;
+                        continue;
+                    }
+
                     if !allow_closing_tag {
                         let close_tag_name = read_word(chars, &mut lookahead_pos)?;
 
@@ -422,17 +501,18 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
                 _ => { 
                     let in_math = state == ParserState::Math || state == ParserState::BigMath;
                     let real_math_tag = next_char == '%' || next_char == '!'  || next_char == ':';
-                    if (in_math && real_math_tag) || !in_math {
+                    if real_math_tag || !in_math {
                          // Opening tag
                         let mut res_pos = pos.clone();
                         let allowed_symbols = if in_math { TagSymbol::PERCENTAGE | TagSymbol::EXCLAMATION_MARK | TagSymbol::COLON }
                                               else       { TagSymbol::NOTHING    | TagSymbol::EXCLAMATION_MARK | TagSymbol::COLON };
 
-                        let result = parse_tag(
+                        let result = parse_tag_with_parents(
                             chars, 
                             &mut res_pos, 
                             allowed_symbols, 
                             in_math,
+                            open_tags,
                             context
                         );
 
@@ -443,30 +523,52 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
                                 *pos = res_pos;
                             }
                             Err(e) => {
-                                return Err(e);
+                                // Can't continue after the end of the file
+                                let mut next_tag_pos = pos.clone();
+                                if e.position.absolute_position + 1 >= chars.len() || skip_to_next_tag(chars, &mut next_tag_pos, in_math).is_err() {
+                                    return Err(e);
+                                }
+
+                                // Remember the error, and continue at the next tag, or at the end of the math
+                                add_error(context, e);
+
+                                let mut name_pos = pos.clone();
+                                advance_position(&mut name_pos, chars)?;
+                                if "?!:%".contains(chars[name_pos.absolute_position]) {
+                                    advance_position(&mut name_pos, chars)?;
+                                }
+                                failed_tags.push(lookahead_word(chars, &mut name_pos)?);
+
+                                *pos = next_tag_pos;
                             },
                         }
                     }
                     else {
                         // Regular math text
-                        content.push(NodeContent::Character((next, pos.clone())));
+                        push_character(&mut content, next, pos, &mut text_end);
                         advance_position(pos, chars)?;
                     }
                 }
             }
         }
         else if next == '&' {
-            let ampersand_after = chars.len() > pos.absolute_position + 1 && chars[pos.absolute_position + 1] == '&';
-            
-            if ampersand_after {
-                double_splits.push(SplitPosition { content_pos: content.len(), file_pos: pos.clone()});
-                advance_position(pos, chars)?;
-            }
-            else {
-                simple_splits.push(SplitPosition { content_pos: content.len(), file_pos: pos.clone()});
-            }
+            match read_entity(chars, pos) {
+                Some(entity) => {
+                    let entity_length = entity.chars().count();
+                    content.push(NodeContent::Entity((entity, pos.clone())));
+                    advance_position_many_with_comments(pos, chars, entity_length)?;
+                },
+                None => {
+                    let ampersand_after = chars.len() > pos.absolute_position + 1 && chars[pos.absolute_position + 1] == '&';
+                    content.push(NodeContent::Ampersand((ampersand_after, pos.clone())));
+                    
+                    if ampersand_after {
+                        advance_position(pos, chars)?;
+                    }
 
-            advance_position(pos, chars)?;
+                    advance_position(pos, chars)?;
+                },
+            }
         }
         else if next == '$' {
             let pos_before_dollar = pos.clone();
@@ -540,9 +642,9 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
     
                     let math_type = if double { ParserState::BigMath } else { ParserState::Math }; 
     
-                    parse_inner_tag(chars, &mut math_tag, pos, math_type, false, context)?;
+                    parse_inner_tag(chars, &mut math_tag, pos, math_type, false, open_tags, context)?;
     
-                    math_tag.source_length = get_positions_difference(&pos, &math_tag.start_position);
+                    math_tag.source_length = get_positions_difference(pos, &math_tag.start_position);
     
                     children.push(math_tag);
                     content.push(NodeContent::Child(children.len() - 1));
@@ -550,60 +652,41 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
             }
         }
         else if next == '`' && state != ParserState::Math && state != ParserState::BigMath { // Code block
-            advance_position_with_comments(pos, chars)?;
-            
-            let double = chars[(*pos).absolute_position] == '`';
-
-            let mut start_inner_position = pos.clone();
-            advance_position_with_comments(&mut start_inner_position, chars)?;
+            let mut code_pos = pos.clone();
+            match parse_code_block(chars, &mut code_pos) {
+                Ok(code_tag) => {
+                    *pos = code_pos;
+                    children.push(code_tag);
+                    content.push(NodeContent::Child(children.len() - 1));
+                },
+                Err(e) => {
+                    // Remember the error, and consider the backticks as regular characters
+                    add_error(context, e);
 
-            // Make big code if two backticks
-            let tag_name = if double {
-                String::from("pre")
+                    for _ in 0..count_backticks(chars, pos) {
+                        push_character(&mut content, '`', pos, &mut text_end);
+                        advance_position_with_comments(pos, chars)?;
+                    }
+                },
             }
-            else {
-                String::from("code")
-            };
-
-            let mut math_tag = Node {
-                name: tag_name,
-                attributes: vec![],
-                children: vec![],
-                content: vec![],
-                auto_closing: false,
-                is_math: false,
-                declaration_symbol: TagSymbol::NOTHING,
-                start_position: pos.clone(),
-                start_inner_position,
-                source_length: 0
+        }
+        else if next.is_whitespace() {
+            let last_is_whitespace = match content.last() {
+                Some(NodeContent::Child(_)) => false,
+                Some(NodeContent::Text((text, _))) => text.ends_with(char::is_whitespace),
+                Some(NodeContent::EscapedCharacter((c, _))) => c.is_whitespace(),
+                Some(NodeContent::Entity(_)) | Some(NodeContent::Ampersand(_)) => false,
+                None => true, // Ignore
             };
-            
-            if double {
-                advance_position_with_comments(pos, chars)?;
-            } 
 
-            parse_inner_tag(chars, &mut math_tag, pos, if double { ParserState::BigCode } else { ParserState::Code }, false, context)?;
+            // Ignore if last chars is already whitespace
+            if !last_is_whitespace {
+                // Add a space
+                push_character(&mut content, ' ', pos, &mut text_end);
 
-            math_tag.source_length = get_positions_difference(&pos, &math_tag.start_position);
-
-            children.push(math_tag);
-            content.push(NodeContent::Child(children.len() - 1));
-        }
-        else if next.is_whitespace() {
-            match content.last() {
-                Some(NodeContent::Child(_)) => {
-                    // Add a space
-                    content.push(NodeContent::Character((' ', pos.clone())));
-                },
-                Some(NodeContent::Character((c, _))) | Some(NodeContent::EscapedCharacter((c, _))) => {
-                    // Ignore if last chars is already whitespace
-                    if !c.is_whitespace() {
-                        // Add a space
-                        content.push(NodeContent::Character((' ', pos.clone())));
-                    }
-                },
-                None => {
-                    // Ignore
+                // The space replaces a line break, so the positions of the next characters can't be found from the start of the run
+                if next == '\n' {
+                    text_end = usize::MAX;
                 }
             }
             
@@ -611,7 +694,7 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
         }
         else {
             // Add character
-            content.push(NodeContent::Character((next, pos.clone())));
+            push_character(&mut content, next, pos, &mut text_end);
             advance_position(pos, chars)?;
         }
 
@@ -626,67 +709,580 @@ fn parse_inner_tag<'a>(chars: &Vec<char>, node: &'a mut Node, pos: &mut FilePosi
         }
     }
 
-    if double_splits.len() > 0 || simple_splits.len() > 0 {
-        double_splits.insert(0, SplitPosition { content_pos: 0, file_pos: start_pos });
-        double_splits.push(SplitPosition { content_pos: content.len(), file_pos: pos.clone() });
+    node.children = children;
+    node.content = content;
+
+    return Ok(());
+}
+
 
-        // Split &&
-        let (actual_content, mut actual_children) = split_ampersands(content, children, &double_splits, "double-amp-split");
+/// Adds a character at the end of the content.
+/// It is appended to the last text run if it directly follows it in the source, so that runs stay contiguous
+///
+/// # Arguments
+/// * `text_end`: where the last text run ends in the source. Updated by this function
+///
+fn push_character(content: &mut Vec<NodeContent>, c: char, pos: &FilePosition, text_end: &mut usize) {
+    match content.last_mut() {
+        Some(NodeContent::Text((text, _))) if *text_end == pos.absolute_position => text.push(c),
+        _ => content.push(NodeContent::Text((String::from(c), pos.clone()))),
+    }
+
+    *text_end = pos.absolute_position + 1;
+}
+
+
+/// The position of a character of a text run, relative to the start of the run. See `get_run_position`
+#[derive(Clone, Copy)]
+pub struct RunOffset<'a> {
+    pub run_start: &'a FilePosition, // The position of the first character of the run
+    pub characters: usize, // The number of characters before it in the run
+    pub lines: usize, // The number of line breaks before it in the run
+    pub line_character: usize, // Its position in its line
+}
 
-        let mut simple_splits_id = 0;
 
-        for (i, child) in actual_children.iter_mut().enumerate() {
+/// Returns the characters of a text run, each with its offset from the start of the run
+pub fn get_run_offsets<'a>(text: &'a str, start: &'a FilePosition) -> impl Iterator<Item = (char, RunOffset<'a>)> + 'a {
+    let mut offset = RunOffset { run_start: start, characters: 0, lines: 0, line_character: start.line_character };
 
-            // Check for simple splits that are inside the child
-            while simple_splits_id < simple_splits.len() && simple_splits[simple_splits_id].content_pos < double_splits[i].content_pos {
-                simple_splits_id += 1;
+    return text.chars().map(move |c| {
+        let res = (c, offset);
+
+        offset.characters += 1;
+        offset.line_character += 1;
+
+        if c == '\n' {
+            offset.lines += 1;
+            offset.line_character = 0;
+        }
+
+        return res;
+    });
+}
+
+
+/// Returns the position in the source of a character of a text run
+pub fn get_run_position(offset: &RunOffset) -> FilePosition {
+    return FilePosition {
+        file_path: offset.run_start.file_path.clone(),
+        absolute_position: offset.run_start.absolute_position + offset.characters,
+        line: offset.run_start.line + offset.lines,
+        line_character: offset.line_character,
+    };
+}
+
+
+/// Moves the cursor after the closing tag it is on
+fn skip_closing_tag(chars: &Vec<char>, pos: &mut FilePosition) -> Result<(), ParseError> {
+    while chars[pos.absolute_position] != '>' {
+        advance_position(pos, chars)?;
+    }
+
+    advance_position(pos, chars)?;
+    return Ok(());
+}
+
+
+/// Moves the cursor to the next "<" after the current character. Used to continue parsing after an error
+///
+/// # Arguments
+/// * `in_math`: the cursor is in math, so it also stops at the dollar that closes it
+///
+fn skip_to_next_tag(chars: &Vec<char>, pos: &mut FilePosition, in_math: bool) -> Result<(), ParseError> {
+    advance_position(pos, chars)?;
+
+    loop {
+        let c = chars[pos.absolute_position];
+        if c == '<' || (in_math && c == '$' && chars[pos.absolute_position - 1] != '\\') {
+            break;
+        }
+
+        advance_position(pos, chars)?;
+    }
+
+    return Ok(());
+}
+
+
+/// Parses a code block: backticks, the code, then the same number of backticks. Nothing is parsed inside.
+/// One backtick makes inline code (`<code>`), more make a big code block (`<pre>`), so that code containing backticks can be written with a longer fence.
+/// A big code block can have an attribute list on the line of the opening fence (``{lang=rust #id .class key=value}).
+/// With three backticks or more, the language can also be written alone (```rust {#id}).
+/// Inline code can have an attribute list right after its closing backtick.
+/// The cursor must be on the first backtick, and is left after the block
+fn parse_code_block(chars: &Vec<char>, pos: &mut FilePosition) -> Result<Node, ParseError> {
+    let start_position = pos.clone();
+    let fence_length = count_backticks(chars, pos);
+    let is_big = fence_length > 1;
+
+    let unterminated_error = ParseError {
+        message: format!("Unterminated code block. Close it with {} backtick{}.", fence_length, if is_big { "s" } else { "" }),
+        position: start_position.clone(),
+        length: fence_length,
+    };
+
+    advance_position_many_with_comments(pos, chars, fence_length).map_err(|_| unterminated_error.clone())?;
+
+    let mut attributes = if is_big { read_code_info(chars, pos, fence_length)? } else { Vec::new() };
+
+    let start_inner_position = pos.clone();
+    let mut code = String::new();
+
+    loop {
+        let next = chars[pos.absolute_position];
+
+        if next == '`' {
+            let count = count_backticks(chars, pos);
+
+            if count == fence_length {
+                break;
             }
 
-            let mut child_splits = Vec::new();
-            while simple_splits_id < simple_splits.len() && simple_splits[simple_splits_id].content_pos < double_splits[i + 1].content_pos {
-                child_splits.push(SplitPosition {
-                    content_pos: simple_splits[simple_splits_id].content_pos - double_splits[i].content_pos,
-                    file_pos: double_splits[i].file_pos.clone(),
+            // Backticks that don't close the block are regular characters
+            for _ in 0..count {
+                code.push('`');
+                advance_position_with_comments(pos, chars).map_err(|_| unterminated_error.clone())?;
+            }
+        }
+        else {
+            code.push(next);
+            advance_position_with_comments(pos, chars).map_err(|_| unterminated_error.clone())?;
+        }
+    }
+
+    advance_position_many_with_comments(pos, chars, fence_length)?;
+
+    // Attributes of inline code
+    if !is_big && chars[pos.absolute_position] == '{' && is_attribute_list_on_line(chars, pos) {
+        read_attribute_list(chars, pos, &mut attributes)?;
+    }
+
+    let source_length = get_positions_difference(pos, &start_position);
+
+    // Nothing is skipped inside the block, so the code is one run
+    let content = if code.is_empty() { vec![] } else { vec![NodeContent::Text((code, start_inner_position.clone()))] };
+
+    return Ok(Node {
+        name: String::from(if is_big { "pre" } else { "code" }),
+        attributes,
+        children: vec![],
+        content,
+        auto_closing: false,
+        is_math: false,
+        declaration_symbol: TagSymbol::NOTHING,
+        start_position,
+        start_inner_position,
+        source_length,
+    });
+}
+
+
+/// Number of backticks that follow each other from the cursor
+fn count_backticks(chars: &Vec<char>, pos: &FilePosition) -> usize {
+    return chars[pos.absolute_position..].iter().take_while(|c| **c == '`').count();
+}
+
+
+/// Reads the language and the attribute list after the opening fence of a big code block, if the rest of the line looks like that.
+/// Otherwise, the line is part of the code and nothing is read. The cursor is left before the end of the line
+///
+/// # Arguments
+/// * `fence_length`: the number of backticks of the fence. The language can only be written alone after three or more,
+///   because blocks with two backticks existed before languages, and their first line may be a word of code
+///
+fn read_code_info(chars: &Vec<char>, pos: &mut FilePosition, fence_length: usize) -> Result<Vec<TagAttribute>, ParseError> {
+    let line_end = chars[pos.absolute_position..].iter().position(|c| *c == '\n').map_or(chars.len(), |i| pos.absolute_position + i);
+    let line: String = chars[pos.absolute_position..line_end].iter().collect();
+
+    let allows_language = fence_length > 2;
+    let trimmed = line.trim();
+    let after_language = if allows_language { trimmed.trim_start_matches(is_language_char).trim_start() } else { trimmed };
+    let looks_like_info = !line.contains('`') 
+        && (after_language.is_empty() || (after_language.starts_with('{') && after_language.ends_with('}')));
+
+    if trimmed.is_empty() || !looks_like_info {
+        return Ok(Vec::new());
+    }
+
+    let info_start = pos.clone();
+    match read_code_info_line(chars, pos, line_end, allows_language) {
+        Err(_) if !allows_language => { // Code of a block with two backticks that only looks like an attribute list
+            *pos = info_start;
+            return Ok(Vec::new());
+        },
+        res => return res,
+    }
+}
+
+
+/// Reads the line of the opening fence for `read_code_info`, when it looks like a language and an attribute list
+fn read_code_info_line(chars: &Vec<char>, pos: &mut FilePosition, line_end: usize, allows_language: bool) -> Result<Vec<TagAttribute>, ParseError> {
+    let mut attributes = Vec::new();
+
+    skip_spaces(chars, pos)?;
+
+    let language_position = pos.clone();
+    let mut language = String::new();
+    while allows_language && is_language_char(chars[pos.absolute_position]) {
+        language.push(chars[pos.absolute_position]);
+        advance_position_with_comments(pos, chars)?;
+    }
+
+    if !language.is_empty() {
+        add_class(&mut attributes, format!("language-{}", language), language_position);
+    }
+
+    skip_spaces(chars, pos)?;
+
+    if chars[pos.absolute_position] == '{' {
+        read_attribute_list(chars, pos, &mut attributes)?;
+
+        skip_spaces(chars, pos)?;
+
+        if pos.absolute_position < line_end {
+            return Err(ParseError {
+                message: String::from("Unexpected text after the attribute list of the code block."),
+                position: pos.clone(),
+                length: line_end - pos.absolute_position,
+            });
+        }
+    }
+
+    return Ok(attributes);
+}
+
+
+fn is_language_char(c: char) -> bool {
+    return c.is_alphanumeric() || "-_+#.".contains(c);
+}
+
+
+/// Is there a "}" after the cursor, on the same line, without backticks in between?
+fn is_attribute_list_on_line(chars: &Vec<char>, pos: &FilePosition) -> bool {
+    for c in &chars[pos.absolute_position..] {
+        match c {
+            '}' => return true,
+            '\n' | '`' => return false,
+            _ => {},
+        }
+    }
+
+    return false;
+}
+
+
+/// Reads an attribute list like {#id .class key=value key="value with spaces"}. The cursor must be on the "{", and is left after the "}".
+/// Classes are added to the "class" attribute of `attributes` if it exists, and "lang=name" becomes the class "language-name"
+fn read_attribute_list(chars: &Vec<char>, pos: &mut FilePosition, attributes: &mut Vec<TagAttribute>) -> Result<(), ParseError> {
+    let start_position = pos.clone();
+    let unmatched_error = ParseError {
+        message: String::from("Unmatched \"{\" in the attribute list of the code block."),
+        position: start_position.clone(),
+        length: 1,
+    };
+
+    advance_position_with_comments(pos, chars).map_err(|_| unmatched_error.clone())?;
+
+    loop {
+        while chars[pos.absolute_position].is_whitespace() {
+            advance_position_with_comments(pos, chars).map_err(|_| unmatched_error.clone())?;
+        }
+
+        let token_position = pos.clone();
+        let first = chars[pos.absolute_position];
+
+        if first == '}' {
+            break;
+        }
+        else if first == '`' {
+            return Err(unmatched_error);
+        }
+        else if first == '.' || first == '#' {
+            advance_position_with_comments(pos, chars).map_err(|_| unmatched_error.clone())?;
+            let value_position = pos.clone();
+            let value = read_attribute_list_word(chars, pos).map_err(|_| unmatched_error.clone())?;
+
+            if value.is_empty() {
+                return Err(ParseError {
+                    message: format!("Expected a name after \"{}\".", first),
+                    position: token_position,
+                    length: 1,
                 });
-                simple_splits_id += 1;
             }
 
-            // If there are & inside, split again
-            if child_splits.len() > 0 {
-                child_splits.insert(0, SplitPosition { content_pos: 0, file_pos: double_splits[i].file_pos.clone() });
-                child_splits.push(SplitPosition { 
-                    content_pos: double_splits[i + 1].content_pos - double_splits[i].content_pos, 
-                    file_pos: double_splits[i + 1].file_pos.clone() 
+            if first == '#' {
+                attributes.push(TagAttribute { name: String::from("id"), value: Some(value), position: Some(token_position), value_position: Some(value_position) });
+            }
+            else {
+                add_class(attributes, value, value_position);
+            }
+        }
+        else {
+            let name = read_attribute_list_word(chars, pos).map_err(|_| unmatched_error.clone())?;
+
+            if name.is_empty() || chars[pos.absolute_position] != '=' {
+                return Err(ParseError {
+                    message: String::from("Expected \"#id\", \".class\" or \"key=value\" in the attribute list of the code block."),
+                    position: token_position,
+                    length: usize::max(name.len(), 1),
                 });
+            }
 
-                let moved_content = std::mem::replace(&mut child.content, vec![]);
-                let moved_children = std::mem::replace(&mut child.children, vec![]);
-                
-                let (actual_child_content, actual_child_children) = split_ampersands(moved_content, moved_children, &child_splits, "amp-split");
-                
-                child.children = actual_child_children;
-                child.content = actual_child_content;
+            advance_position_with_comments(pos, chars).map_err(|_| unmatched_error.clone())?;
+
+            let value_position = pos.clone();
+            let value = if chars[pos.absolute_position] == '"' {
+                advance_position_with_comments(pos, chars)?;
+                read_until_quote(chars, pos)?
             }
+            else {
+                read_attribute_list_word(chars, pos).map_err(|_| unmatched_error.clone())?
+            };
+
+            attributes.push(TagAttribute { name, value: Some(value), position: Some(token_position), value_position: Some(value_position) });
         }
+    }
+
+    advance_position_with_comments(pos, chars)?;
+
+    // "lang=name" is the same as the language written alone after a fence
+    if let Some(lang_index) = attributes.iter().position(|attr| attr.name == "lang") {
+        let lang = attributes.remove(lang_index);
+        add_class(attributes, format!("language-{}", lang.value.expect("Uuh?")), lang.value_position.expect("Uuh?"));
+    }
+
+    return Ok(());
+}
 
-        node.children = actual_children;
-        node.content = actual_content;
-    } 
+
+/// Adds a class to the class attribute, that is created if there is none
+fn add_class(attributes: &mut Vec<TagAttribute>, class_name: String, position: FilePosition) {
+    if let Some(class) = attributes.iter_mut().find(|a| a.name == "class") {
+        let previous = class.value.take().unwrap_or_default();
+        class.value = Some(format!("{} {}", previous, class_name));
+    }
     else {
-        node.children = children;
-        node.content = content;
+        attributes.push(TagAttribute { name: String::from("class"), value: Some(class_name), position: Some(position.clone()), value_position: Some(position) });
+    }
+}
+
+
+/// Reads until whitespace, "=", "}" or a quote, in an attribute list
+fn read_attribute_list_word(chars: &Vec<char>, pos: &mut FilePosition) -> Result<String, ParseError> {
+    let mut res = String::new();
+
+    loop {
+        let c = chars[pos.absolute_position];
+        if c.is_whitespace() || c == '=' || c == '}' || c == '"' || c == '`' {
+            break;
+        }
+
+        res.push(c);
+        advance_position_with_comments(pos, chars)?;
+    }
+
+    return Ok(res);
+}
+
+
+/// Moves the cursor after spaces and tabs, but not after line breaks
+fn skip_spaces(chars: &Vec<char>, pos: &mut FilePosition) -> Result<(), ParseError> {
+    while chars[pos.absolute_position] == ' ' || chars[pos.absolute_position] == '\t' {
+        advance_position_with_comments(pos, chars)?;
     }
 
     return Ok(());
 }
 
 
+/// Returns the HTML character reference that starts at the cursor ("&name;", "&#169;" or "&#xA9;"), or None if the ampersand isn't one
+fn read_entity(chars: &Vec<char>, pos: &FilePosition) -> Option<String> {
+    let mut res = String::from("&");
+    let mut i = pos.absolute_position + 1;
+
+    let is_numeric = chars.get(i) == Some(&'#');
+    let is_hexadecimal = is_numeric && (chars.get(i + 1) == Some(&'x') || chars.get(i + 1) == Some(&'X'));
+    if is_numeric {
+        res.push('#');
+        i += 1;
+    }
+    if is_hexadecimal {
+        res.push(chars[i]);
+        i += 1;
+    }
+
+    let name_start = res.len();
+    while let Some(c) = chars.get(i) {
+        let is_valid = if is_hexadecimal { c.is_ascii_hexdigit() } else if is_numeric { c.is_ascii_digit() } else { c.is_ascii_alphanumeric() };
+        if !is_valid {
+            break;
+        }
+
+        res.push(*c);
+        i += 1;
+    }
+
+    let name = &res[name_start..];
+    let is_valid_name = if is_numeric { !name.is_empty() } else { name.starts_with(|c: char| c.is_ascii_alphabetic()) };
+    if !is_valid_name || chars.get(i) != Some(&';') {
+        return None;
+    }
+
+    res.push(';');
+    return Some(res);
+}
+
+
+/// Replaces the ampersands of the node and of its children.
+/// The content of math and of the custom tags declared with the `split` attribute is split: "&&" separates rows, and "&" separates the cells of a row.
+/// Elsewhere, ampersands are written as text, with a warning.
+///
+/// # Arguments
+/// * `is_definition`: the node is the definition of a custom tag, so its own content is never split
+///
+pub fn split_all_ampersands(node: &mut Node, is_definition: bool, context: &Context) {
+    if node.content.iter().any(|c| matches!(c, NodeContent::Ampersand(_))) {
+        let is_split_tag = !is_definition
+            && node.declaration_symbol == TagSymbol::EXCLAMATION_MARK
+            && context.custom_tags.get(&node.name).is_some_and(|tag| tag.split);
+
+        if node.name == "mathnode" || is_split_tag {
+            split_node(node);
+        }
+        else {
+            write_ampersands_as_text(node);
+        }
+    }
+
+    // The children of a split node are the rows and cells, so the original children are reached through them
+    for child in &mut node.children {
+        split_all_ampersands(child, false, context);
+    }
+}
+
+
+/// Splits the content of the node at its ampersands. Helper for `split_all_ampersands`
+fn split_node(node: &mut Node) {
+    let mut content = Vec::with_capacity(node.content.len());
+    let mut simple_splits: Vec<SplitPosition> = Vec::new();
+    let mut double_splits: Vec<SplitPosition> = Vec::new();
+
+    let mut skip_space = false; // The space after an ampersand is ignored if there is already one before it
+
+    for c in std::mem::take(&mut node.content) {
+        match c {
+            NodeContent::Ampersand((is_double, file_pos)) => {
+                let split = SplitPosition { content_pos: content.len(), file_pos };
+                if is_double {
+                    double_splits.push(split);
+                }
+                else {
+                    simple_splits.push(split);
+                }
+
+                skip_space = match content.last() {
+                    Some(NodeContent::Text((text, _))) => text.ends_with(char::is_whitespace),
+                    Some(NodeContent::EscapedCharacter((c, _))) => c.is_whitespace(),
+                    Some(_) => false,
+                    None => true,
+                };
+            },
+            NodeContent::Text((mut text, mut file_pos)) if skip_space && text.starts_with(' ') => {
+                skip_space = false;
+
+                text.remove(0);
+                if !text.is_empty() {
+                    file_pos.absolute_position += 1;
+                    file_pos.line_character += 1;
+                    content.push(NodeContent::Text((text, file_pos)));
+                }
+            },
+            other => {
+                skip_space = false;
+                content.push(other);
+            },
+        }
+    }
+
+    let mut end_pos = node.start_position.clone();
+    end_pos.absolute_position += node.source_length;
+
+    double_splits.insert(0, SplitPosition { content_pos: 0, file_pos: node.start_inner_position.clone() });
+    double_splits.push(SplitPosition { content_pos: content.len(), file_pos: end_pos });
+
+    // Split &&
+    let children = std::mem::take(&mut node.children);
+    let (actual_content, mut actual_children) = split_ampersands(content, children, &double_splits, "double-amp-split");
+
+    let mut simple_splits_id = 0;
+
+    for (i, child) in actual_children.iter_mut().enumerate() {
+
+        // Check for simple splits that are inside the child
+        while simple_splits_id < simple_splits.len() && simple_splits[simple_splits_id].content_pos < double_splits[i].content_pos {
+            simple_splits_id += 1;
+        }
+
+        let mut child_splits = Vec::new();
+        while simple_splits_id < simple_splits.len() && simple_splits[simple_splits_id].content_pos < double_splits[i + 1].content_pos {
+            child_splits.push(SplitPosition {
+                content_pos: simple_splits[simple_splits_id].content_pos - double_splits[i].content_pos,
+                file_pos: double_splits[i].file_pos.clone(),
+            });
+            simple_splits_id += 1;
+        }
+
+        // If there are & inside, split again
+        if !child_splits.is_empty() {
+            child_splits.insert(0, SplitPosition { content_pos: 0, file_pos: double_splits[i].file_pos.clone() });
+            child_splits.push(SplitPosition {
+                content_pos: double_splits[i + 1].content_pos - double_splits[i].content_pos,
+                file_pos: double_splits[i + 1].file_pos.clone()
+            });
+
+            let moved_content = std::mem::take(&mut child.content);
+            let moved_children = std::mem::take(&mut child.children);
+
+            let (actual_child_content, actual_child_children) = split_ampersands(moved_content, moved_children, &child_splits, "amp-split");
+
+            child.children = actual_child_children;
+            child.content = actual_child_content;
+        }
+    }
+
+    node.children = actual_children;
+    node.content = actual_content;
+}
+
+
+/// Replaces the ampersands of the node by text. Helper for `split_all_ampersands`
+fn write_ampersands_as_text(node: &mut Node) {
+    for c in &mut node.content {
+        if let NodeContent::Ampersand((is_double, file_pos)) = c {
+            let text = String::from(if *is_double { "&&" } else { "&" });
+            let escaped_text = if *is_double { "\\&\\&" } else { "\\&" };
+            log::warning_position(
+                &format!(
+                    "\"{}\" is written as text: only math and the custom tags declared with the split attribute are split into rows and cells. Write \"{}\" to remove this warning.",
+                    text, escaped_text
+                ),
+                file_pos, text.len()
+            );
+
+            let file_pos = file_pos.clone();
+            *c = NodeContent::Text((text, file_pos));
+        }
+    }
+}
+
+
 fn split_ampersands(content: Vec<NodeContent>, children: Vec<Node>, split_positions: &Vec<SplitPosition>, split_tag_name: &str) -> (Vec<NodeContent>, Vec<Node>) {
     let mut actual_content = Vec::with_capacity(split_positions.len() - 1);
     let mut actual_children = Vec::with_capacity(split_positions.len() - 1);
 
-    let mut content_option: Vec<_> = content.into_iter().map(|el| Some(el)).collect();
-    let mut children_option: Vec<_> = children.into_iter().map(|el| Some(el)).collect();
+    let mut content_option: Vec<_> = content.into_iter().map(Some).collect();
+    let mut children_option: Vec<_> = children.into_iter().map(Some).collect();
 
     for i in 0..(split_positions.len() - 1) {
         let pos = &split_positions[i].file_pos;
@@ -701,17 +1297,17 @@ fn split_ampersands(content: Vec<NodeContent>, children: Vec<Node>, split_positi
             declaration_symbol: TagSymbol::NOTHING,
             start_position: pos.clone(),
             start_inner_position: pos.clone(),
-            source_length: split_positions[i + 1].file_pos.absolute_position - split_positions[i].file_pos.absolute_position,
+            source_length: get_source_length(&split_positions[i].file_pos, &split_positions[i + 1].file_pos),
         };
 
-        for content_id in (split_positions[i].content_pos)..(split_positions[i + 1].content_pos) {
-            let content_item = std::mem::replace(&mut content_option[content_id], None).unwrap();
+        for content_item_option in &mut content_option[(split_positions[i].content_pos)..(split_positions[i + 1].content_pos)] {
+            let content_item = content_item_option.take().unwrap();
 
             match content_item {
                 NodeContent::Child(c) => {
                     splitted.content.push(NodeContent::Child(splitted.children.len()));
 
-                    let child = std::mem::replace(&mut children_option[c], None).unwrap();
+                    let child = children_option[c].take().unwrap();
                     splitted.children.push(child);
                 },
                 _ => {
@@ -734,7 +1330,9 @@ pub fn get_node_content_as_str(node: &Node) -> String {
 
     for cont in &node.content {
         match cont {
-            NodeContent::Character((c, _)) | NodeContent::EscapedCharacter((c, _)) => res.push(*c),
+            NodeContent::Text((text, _)) | NodeContent::Entity((text, _)) => res.push_str(text),
+            NodeContent::EscapedCharacter((c, _)) => res.push(*c),
+            NodeContent::Ampersand((is_double, _)) => res.push_str(if *is_double { "&&" } else { "&" }),
             _ => {},
         }
     }
@@ -759,7 +1357,7 @@ pub fn get_attribute_value<'a>(node: &'a Node, attrib_name: &str) -> Result<Opti
 fn expect(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Result<(), ParseError> {
     advance_until_non_whitespace(chars, pos)?;
 
-    if (*pos).absolute_position >= chars.len() {
+    if pos.absolute_position >= chars.len() {
         return Err(ParseError { 
             message: format!("Expected \"{}\", found end of file.", char), 
             position: pos.clone(), 
@@ -767,9 +1365,9 @@ fn expect(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Result<(), P
         });
     }
 
-    if chars[(*pos).absolute_position] != char {
+    if chars[pos.absolute_position] != char {
         return Err(ParseError { 
-            message: format!("Expected \"{}\", found \"{}\".", char, chars[(*pos).absolute_position]), 
+            message: format!("Expected \"{}\", found \"{}\".", char, chars[pos.absolute_position]), 
             position: pos.clone(), 
             length: 1 
         });
@@ -785,7 +1383,7 @@ fn expect(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Result<(), P
 fn expect_allow_eof(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Result<(), ParseError> {
     advance_until_non_whitespace(chars, pos)?;
 
-    if (*pos).absolute_position >= chars.len() {
+    if pos.absolute_position >= chars.len() {
         return Err(ParseError { 
             message: format!("Expected \"{}\", found end of file.", char), 
             position: pos.clone(), 
@@ -793,9 +1391,9 @@ fn expect_allow_eof(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Re
         });
     }
 
-    if chars[(*pos).absolute_position] != char {
+    if chars[pos.absolute_position] != char {
         return Err(ParseError { 
-            message: format!("Expected \"{}\", found \"{}\".", char, chars[(*pos).absolute_position]), 
+            message: format!("Expected \"{}\", found \"{}\".", char, chars[pos.absolute_position]), 
             position: pos.clone(), 
             length: 1 
         });
@@ -812,7 +1410,7 @@ fn expect_allow_eof(chars: &Vec<char>, pos: &mut FilePosition, char: char) -> Re
 fn read_next_char(chars: &Vec<char>, pos: &mut FilePosition) -> Result<char, ParseError> {
     advance_until_non_whitespace(chars, pos)?;
 
-    if (*pos).absolute_position >= chars.len() {
+    if pos.absolute_position >= chars.len() {
         return Err(ParseError { 
             message: String::from("Expected something, found end of file."), 
             position: pos.clone(), 
@@ -820,7 +1418,7 @@ fn read_next_char(chars: &Vec<char>, pos: &mut FilePosition) -> Result<char, Par
         });
     }
 
-    let res = chars[(*pos).absolute_position];
+    let res = chars[pos.absolute_position];
 
     advance_position(pos, chars)?;
     return Ok(res);
@@ -839,6 +1437,8 @@ fn advance_until_non_whitespace(chars: &Vec<char>, pos: &mut FilePosition) -> Re
         });
     }
 
+    skip_comments(chars, pos)?;
+
     while chars[pos.absolute_position].is_whitespace() {
         advance_position(pos, chars)?;
     }
@@ -852,9 +1452,9 @@ fn read_word(chars: &Vec<char>, pos: &mut FilePosition) -> Result<String, ParseE
    advance_until_non_whitespace(chars, pos)?;
     let mut res = Vec::with_capacity(10);
 
-    while (*pos).absolute_position < chars.len() && 
-        (chars[(*pos).absolute_position].is_alphanumeric() || WORD_CHARS.contains(chars[(*pos).absolute_position])) {
-        res.push(chars[(*pos).absolute_position]);
+    while pos.absolute_position < chars.len() && 
+        (chars[pos.absolute_position].is_alphanumeric() || WORD_CHARS.contains(chars[pos.absolute_position])) {
+        res.push(chars[pos.absolute_position]);
         advance_position(pos, chars)?;
     }
 
@@ -863,13 +1463,24 @@ fn read_word(chars: &Vec<char>, pos: &mut FilePosition) -> Result<String, ParseE
 
 
 /// Reads everything until a quote. The cursor is left after the quote (case insensitive, return lowered chars!)
+/// Comments are kept, but quotes inside HTML comments don't end the text
 fn read_until_quote(chars: &Vec<char>, pos: &mut FilePosition) -> Result<String, ParseError> {
     let mut res = Vec::with_capacity(15);
 
     let start_pos = pos.to_owned();
 
-    while chars[pos.absolute_position] != '"' {
-        res.push(chars[(*pos).absolute_position]);
+    loop {
+        let comment_start = pos.absolute_position;
+        if skip_html_comment(chars, pos)? {
+            res.extend_from_slice(&chars[comment_start..pos.absolute_position]);
+            continue;
+        }
+
+        if chars[pos.absolute_position] == '"' {
+            break;
+        }
+
+        res.push(chars[pos.absolute_position]);
 
         match advance_position_with_comments(pos, chars) {
             Ok(()) => {},
@@ -893,67 +1504,119 @@ fn lookahead_word(chars: &Vec<char>, pos: &mut FilePosition) -> Result<String, P
 }
 
 
-/// Advances a position, updating everything in the struct. Ignores "//" and "/**/" comments.
+/// Advances a position, updating everything in the struct. Ignores "//", "/**/" and "<!---->" comments.
 pub fn advance_position(pos: &mut FilePosition, file: &Vec<char>) -> Result<(), ParseError> {
-    let mut in_double_slash_comment = false;
-    let mut in_slash_star_comment = false;
+    advance_position_with_comments(pos, file)?;
+    skip_comments(file, pos)?;
 
+    return Ok(());
+}
+
+
+/// If the cursor is at the beginning of a comment, moves it after the comment (and after the next ones if they follow directly).
+/// Does nothing otherwise
+fn skip_comments(file: &Vec<char>, pos: &mut FilePosition) -> Result<(), ParseError> {
     loop {
-        (*pos).absolute_position += 1;
-        (*pos).line_character += 1;
-
-        if pos.absolute_position >= file.len() {
-            return Err(ParseError { 
-                message: String::from("Unexpected end of file. Maybe you forgot to close a tag."), 
-                position: pos.clone(), 
-                length: 1 
-            });
+        if starts_with(file, pos, "//") {
+            while file[pos.absolute_position] != '\n' {
+                advance_position_with_comments(pos, file)?;
+            }
+            advance_position_with_comments(pos, file)?;
         }
-        
-        let character_before = file[pos.absolute_position - 1];
-        let character = 
-            if pos.absolute_position < file.len() { // Makes sure nto reading after end of file
-                file[pos.absolute_position]
-            } else { '\0' };
-        let character_after = 
-            if pos.absolute_position < file.len() - 1 {
-                file[pos.absolute_position + 1]
-            } else { '\0' };
-            
-        // FIXME: will not count lines for os that use \r
-        if character_before == '\n' {
-            (*pos).line += 1;
-            (*pos).line_character = 0;
+        else if starts_with(file, pos, "/*") {
+            skip_block_comment(file, pos)?;
+        }
+        else if !skip_html_comment(file, pos)? {
+            break;
         }
+    }
 
-        // Keep reading if in comments
-        if in_double_slash_comment {
-            if character == '\n' {
-                in_double_slash_comment = false;
-            }
+    return Ok(());
+}
+
+
+/// Moves the cursor after the "/**/" comment that starts at the cursor. Comments can be nested
+fn skip_block_comment(file: &Vec<char>, pos: &mut FilePosition) -> Result<(), ParseError> {
+    let start_pos = pos.clone();
+    let unterminated_error = |_| ParseError {
+        message: String::from("Unterminated comment. Close it with \"*/\"."),
+        position: start_pos.clone(),
+        length: 2,
+    };
+
+    let mut depth = 0;
+    loop {
+        if starts_with(file, pos, "/*") {
+            depth += 1;
+            advance_position_many_with_comments(pos, file, 2).map_err(unterminated_error)?;
         }
-        else if in_slash_star_comment {
-            if character_before == '*' && character == '/' {
-                in_slash_star_comment = false
+        else if starts_with(file, pos, "*/") {
+            depth -= 1;
+            if depth == 0 {
+                break;
             }
+            advance_position_many_with_comments(pos, file, 2).map_err(unterminated_error)?;
         }
-        else if character == '/' && character_after == '/' { // Detect "//" comments
-            in_double_slash_comment = true;
-        }
-        else if character == '/' && character_after == '*' { // Detect "/**/" comments
-            in_slash_star_comment = true;
+        else {
+            advance_position_with_comments(pos, file).map_err(unterminated_error)?;
         }
-        else { break };
     }
 
+    advance_position_many_with_comments(pos, file, 2)?;
+
     return Ok(());
 }
 
 
+/// If the cursor is at the beginning of a "<!---->" comment, moves it after the comment and returns true
+fn skip_html_comment(file: &Vec<char>, pos: &mut FilePosition) -> Result<bool, ParseError> {
+    if !starts_with(file, pos, "<!--") {
+        return Ok(false);
+    }
+
+    let start_pos = pos.clone();
+    let unterminated_error = |_| ParseError {
+        message: String::from("Unterminated comment. Close it with \"-->\"."),
+        position: start_pos.clone(),
+        length: 4,
+    };
+
+    advance_position_many_with_comments(pos, file, 4).map_err(unterminated_error)?;
+    while !starts_with(file, pos, "-->") {
+        advance_position_with_comments(pos, file).map_err(unterminated_error)?;
+    }
+    advance_position_many_with_comments(pos, file, 3)?;
+
+    return Ok(true);
+}
+
+
+/// Removes the "<!---->" comments of a text. The comments are known to be terminated
+fn remove_html_comments(text: &str) -> String {
+    let mut res = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find("<!--") {
+        res.push_str(&rest[..start]);
+        let end = rest[start + 4..].find("-->").expect("Uuh?");
+        rest = &rest[start + 4 + end + 3..];
+    }
+    res.push_str(rest);
+
+    return res;
+}
+
+
+/// Is `text` written in the file at the position?
+fn starts_with(file: &Vec<char>, pos: &FilePosition, text: &str) -> bool {
+    return text.chars().enumerate().all(|(i, c)| file.get(pos.absolute_position + i) == Some(&c));
+}
+
+
 /// Same as `advance_position`, but reads comments.
 pub fn advance_position_with_comments(pos: &mut FilePosition, file: &Vec<char>) -> Result<(), ParseError> {
-    (*pos).absolute_position += 1;
-    (*pos).line_character += 1;
+    pos.absolute_position += 1;
+    pos.line_character += 1;
 
     if pos.absolute_position >= file.len() {
         return Err(ParseError { 
@@ -967,8 +1630,8 @@ pub fn advance_position_with_comments(pos: &mut FilePosition, file: &Vec<char>)
     
     // FIXME: will not count lines for os that use \r
     if character_before == '\n' {
-        (*pos).line += 1;
-        (*pos).line_character = 0;
+        pos.line += 1;
+        pos.line_character = 0;
     }
         
     return Ok(());
@@ -984,6 +1647,27 @@ fn get_positions_difference(a: &FilePosition, b: &FilePosition) -> usize {
 }
 
 
+/// Returns the number of characters between the positions, or 0 if they are in different files.
+/// Unlike `get_positions_difference`, it accepts content inserted from another file, like the body of an included file
+fn get_source_length(start: &FilePosition, end: &FilePosition) -> usize {
+    if start.file_path != end.file_path {
+        return 0;
+    }
+
+    return end.absolute_position.saturating_sub(start.absolute_position);
+}
+
+
+/// basically call advance_position_with_comments `count` times
+fn advance_position_many_with_comments(pos: &mut FilePosition, file: &Vec<char>, count: usize) -> Result<(), ParseError> {
+    for _ in 0..count {
+        advance_position_with_comments(pos, file)?;
+    }
+
+    return Ok(());
+}
+
+
 /// basically call advance_position `count` times
 pub fn advance_position_many(pos: &mut FilePosition, file: &Vec<char>, count: usize) -> Result<(), ParseError> {
     for _ in 0..count {
@@ -1025,10 +1709,18 @@ pub fn get_tag_from_raw_text(text: &str, is_math: bool, pos: &FilePosition, cont
     let mut fake_pos = pos.clone();
     fake_pos.absolute_position -= pos.absolute_position; // Set a false position, so that 0 is the beginning of the string
 
-    match parse_inner_tag(&chars, &mut res, &mut fake_pos, ParserState::InsideAttributeValue, false, context) {
+    let previous_error_count = context.errors.borrow().len();
+    let res_inner = parse_inner_tag(&chars, &mut res, &mut fake_pos, ParserState::InsideAttributeValue, false, &mut Vec::new(), context);
+
+    // Put the right position again
+    for err in context.errors.borrow_mut().iter_mut().skip(previous_error_count) {
+        err.position.absolute_position += pos.absolute_position;
+    }
+
+    match res_inner {
         Ok(()) => Ok(res),
         Err(mut err) => {
-            err.position.absolute_position += pos.absolute_position; // Put the right position again
+            err.position.absolute_position += pos.absolute_position;
             return Err(err);
         },
     }
@@ -1042,3 +1734,269 @@ fn get_math_close_tag_hint(tag_name: &str) -> String {
     )
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add_custom_tags, parse_snippet, with_context};
+
+    /// Parses a code block written at the start of a file, followed by a line break
+    fn parse_code(text: &str) -> Node {
+        let chars: Vec<char> = format!("{}\n", text).chars().collect();
+        let mut pos = get_start_of_file_position(PathBuf::from("doc.cow"));
+        return parse_code_block(&chars, &mut pos).expect("should parse");
+    }
+
+    fn get_code(node: &Node) -> String {
+        return node.content.iter().map(|c| match c {
+            NodeContent::Text((text, _)) => text.as_str(),
+            _ => panic!("Only text is expected in code"),
+        }).collect();
+    }
+
+    fn get_attribute<'a>(node: &'a Node, name: &str) -> Option<&'a str> {
+        return node.attributes.iter().find(|attr| attr.name == name).and_then(|attr| attr.value.as_deref());
+    }
+
+    /// Parses a file, and returns the positions (line, column) and messages of its errors
+    fn get_parse_errors(source: &str) -> Vec<(usize, usize, String)> {
+        let errors = with_context(|context| parse_file(&PathBuf::from("doc.cow"), &source.chars().collect(), context)).expect_err("should fail");
+        return errors.into_iter().map(|e| (e.position.line, e.position.line_character, e.message)).collect();
+    }
+
+    #[test]
+    fn first_word_of_two_backtick_block_is_code() {
+        let node = parse_code("``foo\nbar``");
+
+        assert_eq!(get_code(&node), "foo\nbar");
+        assert_eq!(get_attribute(&node, "class"), None);
+    }
+
+    #[test]
+    fn two_backtick_block_takes_attribute_list() {
+        let node = parse_code("``{lang=rust #main .small}\nfn main() {}\n``");
+
+        assert_eq!(get_code(&node), "\nfn main() {}\n");
+        assert_eq!(get_attribute(&node, "class"), Some("small language-rust"));
+        assert_eq!(get_attribute(&node, "id"), Some("main"));
+    }
+
+    #[test]
+    fn two_backtick_block_starting_like_attribute_list_is_code() {
+        let node = parse_code("``{\"a\": 1}\n``");
+
+        assert_eq!(get_code(&node), "{\"a\": 1}\n");
+        assert!(node.attributes.is_empty());
+    }
+
+    #[test]
+    fn three_backtick_block_takes_language() {
+        let node = parse_code("```rust {#main}\nlet a = ``;\n```");
+
+        assert_eq!(get_code(&node), "\nlet a = ``;\n");
+        assert_eq!(get_attribute(&node, "class"), Some("language-rust"));
+        assert_eq!(get_attribute(&node, "id"), Some("main"));
+    }
+
+    #[test]
+    fn block_is_closed_by_the_same_number_of_backticks() {
+        let node = parse_code("````\n```rust\n`a` ``b``\n```\n````");
+
+        assert_eq!(node.name, "pre");
+        assert_eq!(get_code(&node), "\n```rust\n`a` ``b``\n```\n");
+        assert!(node.attributes.is_empty());
+    }
+
+    #[test]
+    fn inline_code_takes_attribute_list_after_it() {
+        let node = parse_code("`let a;`{lang=rust .small} b");
+
+        assert_eq!(node.name, "code");
+        assert_eq!(get_code(&node), "let a;");
+        assert_eq!(get_attribute(&node, "class"), Some("small language-rust"));
+        assert_eq!(node.source_length, "`let a;`{lang=rust .small}".len());
+
+        // Braces that aren't an attribute list are left for the text
+        let node = parse_code("`a`{ b");
+        assert!(node.attributes.is_empty());
+        assert_eq!(node.source_length, 3);
+    }
+
+    #[test]
+    fn unterminated_code_block_is_reported_at_its_fence() {
+        let errors = get_parse_errors("<body>\nText ```\ncode `` ````\n</body>");
+
+        // The backticks after the fence are parsed again while recovering, so only the first error is checked
+        assert_eq!((errors[0].0, errors[0].1), (1, 5));
+        assert_eq!(errors[0].2, "Unterminated code block. Close it with 3 backticks.");
+    }
+
+    #[test]
+    fn void_elements_need_no_slash() {
+        let node = with_context(|context| parse_snippet("<p>a<br>b<img src=\"x.png\" alt=\"\"><br/>c<svg><path d=\"M0\"></path></svg></p>", context));
+
+        let names: Vec<&str> = node.children.iter().map(|child| child.name.as_str()).collect();
+        assert_eq!(names, vec!["br", "img", "br", "svg"]);
+        assert!(node.children[..3].iter().all(|child| child.auto_closing));
+        assert_eq!(get_attribute_value(&node.children[1], "src"), Ok(Some("x.png")));
+        assert_eq!(get_node_content_as_str(&node), "abc");
+
+        // SVG paths can have content, so they still need the slash to be auto-closing
+        assert!(!node.children[3].children[0].auto_closing);
+    }
+
+    #[test]
+    fn closing_tag_of_void_element_is_an_error() {
+        let errors = get_parse_errors("<body>\n<p>a<br>b</br></p>\n</body>");
+
+        assert_eq!((errors[0].0, errors[0].1), (1, 9));
+        assert!(errors[0].2.starts_with("Unexpected closing tag \"</br>\". \"br\" is a void element"));
+    }
+
+    #[test]
+    fn parsing_continues_after_an_error() {
+        let errors = get_parse_errors("<body>\n<p>x</q>\n<b>y</i>\n</body>");
+
+        let positions: Vec<(usize, usize)> = errors.iter()
+            .filter(|(_, _, message)| message.starts_with("Unexpected closing tag"))
+            .map(|(line, column, _)| (*line, *column))
+            .collect();
+        assert_eq!(positions, vec![(1, 4), (2, 4)]); // At the "<" of the closing tags
+    }
+
+    #[test]
+    fn recovery_in_math_stops_at_the_closing_dollar() {
+        // Only the error of the tag is reported, not the closing tags after the math
+        for source in ["<body>\n<p>$a <%bad/> b$ more text</p><p>ok</p>\n</body>", "<body>\n$a <%unknownop/> b$\n</body>"] {
+            let errors = get_parse_errors(source);
+
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert_eq!((errors[0].0, errors[0].1), (1, source.find('%').unwrap() - 7));
+            assert!(errors[0].2.contains("should not be auto-closing"));
+        }
+    }
+
+    #[test]
+    fn errors_are_sorted_by_position() {
+        let errors = get_parse_errors("<body>\n$a <%x/> b$\n<p>y</q>\n$c <%z/>$\n</body>");
+
+        let positions: Vec<(usize, usize)> = errors.iter().map(|(line, column, _)| (*line, *column)).collect();
+        assert!(positions.len() >= 3);
+        assert!(positions.windows(2).all(|p| p[0] < p[1]), "{:?}", positions);
+    }
+
+    #[test]
+    fn stray_ampersands_are_text_with_a_warning() {
+        let (node, warning_count) = with_context(|context| {
+            let mut node = parse_snippet("<p>AT&T, a && b, AT\\&T, &copy;</p>", context);
+            let (_, warning_count) = log::count_warnings(|| split_all_ampersands(&mut node, false, context));
+            return (node, warning_count);
+        });
+
+        assert_eq!(warning_count, 2);
+        assert!(node.content.iter().all(|c| !matches!(c, NodeContent::Ampersand(_))));
+        assert_eq!(get_node_content_as_str(&node), "AT&T, a && b, AT&T, &copy;");
+    }
+
+    #[test]
+    fn entities_are_read_whole() {
+        let read = |text: &str| read_entity(&text.chars().collect(), &get_start_of_file_position(PathBuf::from("doc.cow")));
+
+        assert_eq!(read("&copy; a").as_deref(), Some("&copy;"));
+        assert_eq!(read("&#169;").as_deref(), Some("&#169;"));
+        assert_eq!(read("&#xA9;").as_deref(), Some("&#xA9;"));
+        assert_eq!(read("&T, b"), None);
+        assert_eq!(read("&#x;"), None);
+    }
+
+    #[test]
+    fn split_tags_are_split_without_the_split_attribute() {
+        with_context(|context| {
+            add_custom_tags("<!grid split :inner><:inner/></grid>", context);
+            assert!(context.custom_tags["grid"].split);
+            assert_eq!(context.custom_tags["grid"].content.attributes.len(), 1); // Only the argument
+
+            let mut node = parse_snippet("<p><!grid>a & b && c</grid> d & e</p>", context);
+            split_all_ampersands(&mut node, false, context);
+
+            let grid = &node.children[0];
+            let rows: Vec<usize> = grid.children.iter().map(|row| row.children.len()).collect();
+            assert!(grid.children.iter().all(|row| row.name == "double-amp-split"));
+            assert_eq!(rows, vec![2, 0]); // The last row has no cell separator
+            assert_eq!(get_node_content_as_str(&node), " d & e");
+        });
+    }
+
+    #[test]
+    fn html_comments_are_skipped_in_text() {
+        let node = with_context(|context| parse_snippet("<p>a<!-- x <b> -->b<!---->c</p>", context));
+        assert_eq!(get_node_content_as_str(&node), "abc");
+        assert!(node.children.is_empty());
+    }
+
+    #[test]
+    fn html_comments_are_removed_from_attribute_values() {
+        assert_eq!(remove_html_comments("a<!-- x -->b<!---->c"), "abc");
+
+        let node = with_context(|context| parse_snippet("<p title=\"a<!-- x -->b\">c</p>", context));
+        assert_eq!(get_attribute(&node, "title"), Some("ab"));
+    }
+
+    #[test]
+    fn unterminated_html_comment_is_reported_at_its_start() {
+        let chars: Vec<char> = "ab <!-- x -- >".chars().collect();
+        let mut pos = get_start_of_file_position(PathBuf::from("doc.cow"));
+        pos.absolute_position = 3;
+        pos.line_character = 3;
+
+        let err = skip_html_comment(&chars, &mut pos).expect_err("should fail");
+        assert_eq!((err.position.absolute_position, err.length), (3, 4));
+        assert!(err.message.starts_with("Unterminated comment"));
+
+        let errors = get_parse_errors("<body>\n  <!-- x\n</body>\n");
+        assert_eq!((errors[0].0, errors[0].1), (1, 2));
+    }
+
+    #[test]
+    fn block_comments_can_be_nested() {
+        let chars: Vec<char> = "/* a /* b */ c */d".chars().collect();
+        let mut pos = get_start_of_file_position(PathBuf::from("doc.cow"));
+
+        skip_block_comment(&chars, &mut pos).expect("should skip");
+        assert_eq!(chars[pos.absolute_position], 'd');
+    }
+
+    #[test]
+    fn unclosed_block_comment_is_reported_at_its_start() {
+        // The inner comment is closed, not the outer one
+        let errors = get_parse_errors("<body>\n  x /* a /* b */ c\n</body>\n");
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].0, errors[0].1), (1, 4));
+        assert!(errors[0].2.starts_with("Unterminated comment. Close it with \"*/\""));
+    }
+
+    #[test]
+    fn text_is_stored_as_runs() {
+        let node = with_context(|context| parse_snippet("<p>ab\ncd<b>x</b>ef</p>", context));
+
+        let runs: Vec<(&str, usize, usize)> = node.content.iter().filter_map(|c| match c {
+            NodeContent::Text((text, pos)) => Some((text.as_str(), pos.line, pos.line_character)),
+            _ => None,
+        }).collect();
+        // The line break is replaced by a space, so the next run starts after it
+        assert_eq!(runs, vec![("ab ", 0, 3), ("cd", 1, 0), ("ef", 1, 10)]);
+    }
+
+    #[test]
+    fn run_offsets_give_the_position_of_each_character() {
+        let start = FilePosition { file_path: std::rc::Rc::new(PathBuf::from("doc.cow")), absolute_position: 10, line: 2, line_character: 4 };
+
+        let positions: Vec<(char, usize, usize, usize)> = get_run_offsets("a\nbc", &start)
+            .map(|(c, offset)| (c, get_run_position(&offset)))
+            .map(|(c, pos)| (c, pos.absolute_position, pos.line, pos.line_character))
+            .collect();
+        assert_eq!(positions, vec![('a', 10, 2, 4), ('\n', 11, 2, 5), ('b', 12, 3, 0), ('c', 13, 3, 1)]);
+    }
+}
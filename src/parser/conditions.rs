@@ -0,0 +1,203 @@
+use crate::{log, Context};
+use crate::parser::{Node, NodeContent, ParseError, TagSymbol};
+
+// Evaluates conditional blocks: <!if flag="name">...</if>, optionally followed by <!else>...</else>
+// The blocks are replaced by the content that was chosen, before custom tags are instantiated
+
+
+/// Replaces the conditional blocks inside the node by their content if the flag is set, or by the content of the else block that follows them otherwise.
+/// Flags that are not in `context.flags` are considered not set, and a warning is reported, once for each if block.
+pub fn evaluate_conditions(node: &mut Node, context: &Context) -> Result<(), ParseError> {
+    let mut children: Vec<Option<Node>> = std::mem::take(&mut node.children).into_iter().map(Some).collect();
+    let content = std::mem::take(&mut node.content);
+
+    let mut i = 0;
+    while i < content.len() {
+        match &content[i] {
+            NodeContent::Child(child_id) if is_directive(children[*child_id].as_ref().expect("Uuh?"), "if") => {
+                let if_node = children[*child_id].take().expect("Uuh?");
+                let is_set = get_condition_value(&if_node, context)?;
+
+                // Look for an else block, only separated by whitespace
+                let mut next = i + 1;
+                while let Some(NodeContent::Text((text, _))) = content.get(next) {
+                    if !text.trim().is_empty() {
+                        break;
+                    }
+                    next += 1;
+                }
+
+                let mut else_node = None;
+                if let Some(NodeContent::Child(else_id)) = content.get(next) {
+                    if is_directive(children[*else_id].as_ref().expect("Uuh?"), "else") {
+                        else_node = children[*else_id].take();
+                        i = next; // Skip the whitespace and the else block
+                    }
+                }
+
+                let chosen = if is_set { Some(if_node) } else { else_node };
+                match chosen {
+                    Some(mut chosen) => {
+                        evaluate_conditions(&mut chosen, context)?;
+                        insert_content(node, chosen);
+                    },
+                    None => {
+                        // Nothing replaces the block, so the whitespace around it would be doubled: only the one after it is kept
+                        let followed_by_whitespace = matches!(content.get(i + 1), Some(NodeContent::Text((text, _))) if text.starts_with(char::is_whitespace));
+                        if followed_by_whitespace {
+                            trim_end_of_content(node);
+                        }
+                    },
+                }
+            },
+            NodeContent::Child(child_id) => {
+                let mut child = children[*child_id].take().expect("Uuh?");
+
+                if is_directive(&child, "else") {
+                    return Err(ParseError {
+                        message: String::from("This else block doesn't follow an if block. Only whitespace can separate them."),
+                        position: child.start_position,
+                        length: child.name.len() + 2,
+                    });
+                }
+
+                evaluate_conditions(&mut child, context)?;
+
+                node.content.push(NodeContent::Child(node.children.len()));
+                node.children.push(child);
+            },
+            other => node.content.push(other.clone()),
+        }
+
+        i += 1;
+    }
+
+    return Ok(());
+}
+
+
+pub fn is_directive(node: &Node, name: &str) -> bool {
+    return node.declaration_symbol == TagSymbol::EXCLAMATION_MARK && node.name == name;
+}
+
+
+/// Is the flag of the if block set?
+fn get_condition_value(if_node: &Node, context: &Context) -> Result<bool, ParseError> {
+    let flag_name = match crate::parser::get_attribute_value(if_node, "flag") {
+        Ok(Some(name)) => name,
+        _ => {
+            return Err(ParseError {
+                message: String::from("An if block needs a flag attribute with a value, like this: <!if flag=\"name\">."),
+                position: if_node.start_position.clone(),
+                length: if_node.name.len() + 2,
+            });
+        },
+    };
+
+    match context.flags.get(flag_name) {
+        Some(is_set) => return Ok(*is_set),
+        None => {
+            // The blocks in custom tags are evaluated each time the tag is used
+            let position = &if_node.start_position;
+            let is_new = context.unknown_flags_reported.borrow_mut().insert((position.file_path.to_path_buf(), position.absolute_position));
+            if !is_new {
+                return Ok(false);
+            }
+
+            log::warning_position(
+                &format!("Unknown flag \"{}\", it is considered not set. Declare it in the head with <flag>{}</flag>, or set it with --flag {}.", flag_name, flag_name, flag_name),
+                &if_node.start_position, if_node.name.len() + 2
+            );
+            return Ok(false);
+        },
+    }
+}
+
+
+/// Removes the whitespace at the end of the text that ends the content of the node, if any
+fn trim_end_of_content(node: &mut Node) {
+    if let Some(NodeContent::Text((text, _))) = node.content.last_mut() {
+        text.truncate(text.trim_end().len());
+
+        if text.is_empty() {
+            node.content.pop();
+        }
+    }
+}
+
+
+/// Adds the content and the children of `source` at the end of `node`
+pub fn insert_content(node: &mut Node, source: Node) {
+    let mut children: Vec<Option<Node>> = source.children.into_iter().map(Some).collect();
+
+    for c in source.content {
+        match c {
+            NodeContent::Child(child_id) => {
+                node.content.push(NodeContent::Child(node.children.len()));
+                node.children.push(children[child_id].take().expect("Uuh?"));
+            },
+            other => node.content.push(other),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::get_node_content_as_str;
+    use crate::test_util::{compile_files, parse_snippet, with_context};
+
+    /// Evaluates the conditions of the tag `instance_count` times, like a custom tag used that many times.
+    /// Returns the text of the last result, and the number of warnings
+    fn evaluate(source: &str, flags: &[(&str, bool)], instance_count: usize) -> (String, usize) {
+        return with_context(|context| {
+            context.flags = flags.iter().map(|(name, is_set)| (String::from(*name), *is_set)).collect();
+            let node = parse_snippet(source, context);
+
+            let (text, warning_count) = crate::log::count_warnings(|| {
+                let mut text = String::new();
+                for _ in 0..instance_count {
+                    let mut instance = node.clone();
+                    evaluate_conditions(&mut instance, context).expect("should evaluate");
+                    text = get_node_content_as_str(&instance);
+                }
+                text
+            });
+            return (text, warning_count);
+        });
+    }
+
+    #[test]
+    fn unknown_flag_is_reported_once_per_block() {
+        let (text, warning_count) = evaluate("<p><!if flag=\"draft\">draft</if><!else>final</else></p>", &[], 2);
+
+        assert_eq!(text, "final");
+        assert_eq!(warning_count, 1);
+    }
+
+    #[test]
+    fn flag_chooses_the_block() {
+        let source = "<p><!if flag=\"draft\">draft</if> <!else>final</else></p>";
+
+        assert_eq!(evaluate(source, &[("draft", true)], 1), (String::from("draft"), 0));
+        assert_eq!(evaluate(source, &[("draft", false)], 1), (String::from("final"), 0));
+    }
+
+    #[test]
+    fn removed_block_leaves_one_space() {
+        let (text, _) = evaluate("<p>no <!if flag=\"acme\">X</if> ACME</p>", &[("acme", false)], 1);
+        assert_eq!(text, "no ACME");
+    }
+
+    #[test]
+    fn head_flags_are_used() {
+        let (html, diagnostics) = compile_files(&[
+            ("doc.cow", "<document><head><flag set>draft</flag></head><body><!if flag=\"draft\">draft</if><!else>final</else></body></document>"),
+        ]);
+
+        let html = html.expect("should compile");
+        assert!(html.contains("draft") && !html.contains("final"));
+        assert!(diagnostics.is_empty());
+    }
+}
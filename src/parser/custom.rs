@@ -1,10 +1,37 @@
 
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use crate::log;
 use crate::parser;
 use crate::parser::{Node, FilePosition, TagSymbol};
 
 use super::{ParseError, TagAttribute};
 
+/// Names of the HTML, SVG and MathML elements, in lowercase. Custom tags can't have these names without the `override` attribute, see `check_tag_name`
+const ELEMENT_NAMES: [&str; 218] = [
+    // HTML
+    "a", "abbr", "acronym", "address", "area", "article", "aside", "audio", "b", "base", "bdi", "bdo", "big", "blockquote", "body", "br", "button",
+    "canvas", "caption", "center", "cite", "code", "col", "colgroup", "data", "datalist", "dd", "del", "details", "dfn", "dialog", "dir", "div", "dl", "dt",
+    "em", "embed", "fieldset", "figcaption", "figure", "font", "footer", "form", "frame", "frameset", "h1", "h2", "h3", "h4", "h5", "h6", "head", "header",
+    "hgroup", "hr", "html", "i", "iframe", "img", "input", "ins", "kbd", "label", "legend", "li", "link", "main", "map", "mark", "marquee", "menu", "meta",
+    "meter", "nav", "nobr", "noframes", "noscript", "object", "ol", "optgroup", "option", "output", "p", "param", "picture", "plaintext", "pre", "progress",
+    "q", "rp", "rt", "ruby", "s", "samp", "script", "search", "section", "select", "slot", "small", "source", "span", "strike", "strong", "style", "sub",
+    "summary", "sup", "table", "tbody", "td", "template", "textarea", "tfoot", "th", "thead", "time", "title", "tr", "track", "tt", "u", "ul", "var",
+    "video", "wbr", "xmp",
+    // SVG
+    "animate", "animatemotion", "animatetransform", "circle", "clippath", "defs", "desc", "ellipse", "feblend", "fecolormatrix", "fecomponenttransfer",
+    "fecomposite", "feconvolvematrix", "fediffuselighting", "fedisplacementmap", "fedistantlight", "fedropshadow", "feflood", "fefunca", "fefuncb",
+    "fefuncg", "fefuncr", "fegaussianblur", "feimage", "femerge", "femergenode", "femorphology", "feoffset", "fepointlight", "fespecularlighting",
+    "fespotlight", "fetile", "feturbulence", "filter", "foreignobject", "g", "image", "line", "lineargradient", "marker", "mask", "metadata", "mpath",
+    "path", "pattern", "polygon", "polyline", "radialgradient", "rect", "set", "stop", "svg", "switch", "symbol", "text", "textpath", "tspan", "use", "view",
+    // MathML
+    "math", "annotation", "annotation-xml", "maction", "menclose", "merror", "mfenced", "mfrac", "mi", "mmultiscripts", "mn", "mo", "mover", "mpadded",
+    "mphantom", "mprescripts", "mroot", "mrow", "ms", "mspace", "msqrt", "mstyle", "msub", "msubsup", "msup", "mtable", "mtd", "mtext", "mtr", "munder",
+    "munderover", "semantics",
+];
+
+
 /// Represents a tag created by the user. Also used for math operators
 #[derive(Clone)]
 pub struct CustomTag {
@@ -12,7 +39,8 @@ pub struct CustomTag {
     pub is_math: bool,
     pub content: Node,
     pub alias: Option<String>,
-    pub infix_alias: bool
+    pub infix_alias: bool,
+    pub split: bool, // Is its content split into rows and cells by "&&" and "&"?
 }
 
 
@@ -29,95 +57,184 @@ pub type TagHash = HashMap<String, CustomTag>;
 /// * `is_default`: is it a default file (will ignore aliases etc.)
 /// * `file_path`: the path to the file name 
 ///
+/// # Returns
+/// * the hash with the new tags, or all the errors found in the file
+///
 pub fn parse_custom_tags(file: &Vec::<char>, pos: &mut FilePosition, hash: TagHash<>, args: &crate::Args, is_default: bool, 
-    default_dir: &std::path::PathBuf, file_path: &std::path::PathBuf) -> Result<TagHash, parser::ParseError> {
+    default_dir: &std::path::PathBuf, file_path: &std::path::PathBuf) -> Result<TagHash, Vec<parser::ParseError>> {
     let mut context = parser::Context { 
         args, 
         custom_tags: hash, 
         ignore_aliases: is_default, 
         default_dir: default_dir.clone(), 
-        main_file_path: file_path.clone()
+        main_file_path: file_path.clone(),
+        dependencies: RefCell::new(Vec::new()),
+        pdf_output_path: PathBuf::new(),
+        html_output_path: PathBuf::new(),
+        config: crate::config::Config::default(),
+        flags: HashMap::new(),
+        errors: RefCell::new(Vec::new()),
+        unknown_flags_reported: RefCell::new(HashSet::new()),
     };
 
     loop { // Repeat until end of the file
-        let mut node = parser::parse_tag(file, pos, TagSymbol::QUESTION_MARK | TagSymbol::EXCLAMATION_MARK, false, &context)?;
-
-        // Check if a "?" was added
-        let is_math = node.declaration_symbol == TagSymbol::QUESTION_MARK; 
-
-        // Parse math immediately
-        super::math::parse_all_math(&mut node, is_math, &context)?;
-
-        let mut alias = None;
-        let mut infix_alias = false;
-
-        let mut arguments = Vec::with_capacity(node.attributes.len());
-        for attr in &node.attributes {
-
-            let mut chars = attr.name.chars();
-            if chars.next() == Some(':') { // It's an argument
-                if attr.value.is_some() {
-                    return Err(parser::ParseError {
-                        message: format!(
-                            "In custom tag definition, the argument \"{}\" has value \"{}\", but it shouldn't have any. You should remove either the colon to make it a regular attribute, or the value.", 
-                            attr.name, 
-                            attr.value.clone().unwrap()
-                        ),
-                        position: node.start_position.clone(),
-                        length: node.start_inner_position.absolute_position - node.start_position.absolute_position
-                    });
-                }
+        let node = match parser::parse_tag(file, pos, TagSymbol::QUESTION_MARK | TagSymbol::EXCLAMATION_MARK, false, &context) {
+            Ok(node) => node,
+            Err(err) => { // The end of the definition is unknown, so the next ones can't be read
+                parser::add_error(&context, err);
+                break;
+            },
+        };
 
-                let arg_name = chars.collect();
-                arguments.push(arg_name);
-            }
-            else if attr.name == "alias" { // "alias" attribute
+        // Errors in the definition don't prevent reading the next ones
+        match get_custom_tag(node, args, &context) {
+            Ok(tag) => { context.custom_tags.insert(tag.content.name.clone(), tag); },
+            Err(err) => parser::add_error(&context, err),
+        }
 
-                // Prevent two alias attributes
-                if alias != None {
-                    return Err(ParseError {
-                        message: String::from("Custom tag can have only 1 alias. Two alias attributes were found."),
-                        position: attr.position.clone().expect("Error probably because the attribute is created by internal code..."),
-                        length: attr.name.chars().count(),
-                    });
-                }
+        match super::advance_until_non_whitespace(file, pos) {
+            Ok(()) => {},
+            Err(_) => break,
+        }
+    }
 
-                match &attr.value {
-                    Some(value) => alias = Some(value.clone()),
-                    // Throw error if no value
-                    None => return Err(ParseError {
-                        message: String::from("The attribute alias is used to define an alias for the tag, so the attribute should have a value."),
-                        position: attr.position.clone().expect("Error probably because the attribute is created by internal code..."),
-                        length: attr.name.chars().count(),
-                    }),
-                }
+    let custom_tags = std::mem::take(&mut context.custom_tags);
+    return parser::collect_errors(Ok(custom_tags), &context);
+}
+
+
+/// Creates a custom tag from its definition. Helper for `parse_custom_tags`
+fn get_custom_tag(mut node: Node, args: &crate::Args, context: &parser::Context) -> Result<CustomTag, ParseError> {
+    // Check if a "?" was added
+    let is_math = node.declaration_symbol == TagSymbol::QUESTION_MARK; 
+
+    let mut alias = None;
+    let mut infix_alias = false;
+    let mut split = false;
+    let mut is_override = false;
+
+    let mut arguments = Vec::with_capacity(node.attributes.len());
+    for attr in &node.attributes {
+
+        let mut chars = attr.name.chars();
+        if chars.next() == Some(':') { // It's an argument
+            if attr.value.is_some() {
+                return Err(parser::ParseError {
+                    message: format!(
+                        "In custom tag definition, the argument \"{}\" has value \"{}\", but it shouldn't have any. You should remove either the colon to make it a regular attribute, or the value.", 
+                        attr.name, 
+                        attr.value.clone().unwrap()
+                    ),
+                    position: node.start_position.clone(),
+                    length: node.start_inner_position.absolute_position - node.start_position.absolute_position
+                });
             }
-            else if attr.name == "infix-alias" {
-                infix_alias = true;
+
+            let arg_name = chars.collect();
+            arguments.push(arg_name);
+        }
+        else if attr.name == "alias" { // "alias" attribute
+
+            // Prevent two alias attributes
+            if alias.is_some() {
+                return Err(ParseError {
+                    message: String::from("Custom tag can have only 1 alias. Two alias attributes were found."),
+                    position: attr.position.clone().expect("Error probably because the attribute is created by internal code..."),
+                    length: attr.name.chars().count(),
+                });
             }
-            else {
-                // Real attribute: do nothing
+
+            match &attr.value {
+                Some(value) => alias = Some(value.clone()),
+                // Throw error if no value
+                None => return Err(ParseError {
+                    message: String::from("The attribute alias is used to define an alias for the tag, so the attribute should have a value."),
+                    position: attr.position.clone().expect("Error probably because the attribute is created by internal code..."),
+                    length: attr.name.chars().count(),
+                }),
             }
         }
-        
-        // Check for incorrect or missing colon tags inside
-        check_colon_tags(&node, &arguments)?;
-
-        context.custom_tags.insert(node.name.clone(), CustomTag {
-            arguments,
-            is_math,
-            content: node,
-            alias,
-            infix_alias,
-        }); 
-
-        match super::advance_until_non_whitespace(file, pos) {
-            Ok(()) => {},
-            Err(_) => break,
+        else if attr.name == "infix-alias" {
+            infix_alias = true;
         }
+        else if attr.name == "split" {
+            split = true;
+        }
+        else if attr.name == "override" {
+            is_override = true;
+        }
+        else {
+            // Real attribute: do nothing
+        }
+    }
+
+    // These only configure the definition, so they aren't written in the instances. Arguments are found by their index in the attributes too
+    node.attributes.retain(|attr| attr.name != "split" && attr.name != "override");
+
+    // Math operators are only used in math, where they can't be mixed up with the elements of the document
+    if node.declaration_symbol == TagSymbol::EXCLAMATION_MARK {
+        check_tag_name(&node, is_override)?;
+    }
+    
+    // Variables are replaced before math is parsed, so that their values can be used in math
+    replace_variables(&mut node, &args.variables, &arguments);
+
+    super::split_all_ampersands(&mut node, true, context);
+
+    // Parse math immediately
+    super::math::parse_all_math(&mut node, is_math, context)?;
+
+    // Check for incorrect or missing colon tags inside
+    check_colon_tags(&node, &arguments, context);
+
+    return Ok(CustomTag {
+        arguments,
+        is_math,
+        content: node,
+        alias,
+        infix_alias,
+        split,
+    });
+}
+
+
+/// Makes sure the name of a `!` custom tag can be used as an element name, and doesn't replace an existing element, unless `is_override` is set.
+/// Helper for `get_custom_tag`
+fn check_tag_name(node: &Node, is_override: bool) -> Result<(), ParseError> {
+    let name_length = node.name.chars().count() + 1;
+
+    let is_valid_name = node.name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && node.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !is_valid_name {
+        return Err(ParseError {
+            message: format!("Invalid custom tag name \"{}\". The name should start with a letter, and contain only letters, digits, \"-\" and \"_\".", node.name),
+            position: node.start_position.clone(),
+            length: name_length,
+        });
+    }
+
+    let lowercase_name = node.name.to_lowercase();
+    let is_element_name = ELEMENT_NAMES.contains(&lowercase_name.as_str()) || super::AUTOCLOSING_TAGS.contains(&lowercase_name.as_str());
+
+    if is_element_name && !is_override {
+        return Err(ParseError {
+            message: format!(
+                "The custom tag \"{}\" has the name of an HTML element, so they would be mixed up in the documents. \
+                Choose another name, or add the \"override\" attribute if you really want to replace it.",
+                node.name
+            ),
+            position: node.start_position.clone(),
+            length: name_length,
+        });
+    }
+    else if !is_element_name && is_override {
+        log::warning_position(
+            &format!("The custom tag \"{}\" has the \"override\" attribute, but there is no HTML element with that name. Remove the attribute.", node.name),
+            &node.start_position, name_length
+        );
     }
 
-    return Ok(context.custom_tags);
+    return Ok(());
 }
 
 
@@ -184,7 +301,7 @@ fn instantiate_tag_inner(tag: &CustomTag, node: &Node, arguments: &Vec<Node>, co
     for i in 0..node.attributes.len() {
         let attr = &node.attributes[i];
 
-        if attr.name.chars().next() == Some(':') && copy_arguments_into_attributes {
+        if attr.name.starts_with(':') && copy_arguments_into_attributes {
             res_attibutes.push(TagAttribute {
                 name: attr.name.clone(),
                 value: Some(crate::parser::get_node_content_as_str(&arguments[i])),
@@ -192,9 +309,19 @@ fn instantiate_tag_inner(tag: &CustomTag, node: &Node, arguments: &Vec<Node>, co
                 value_position: attr.value_position.clone(),
             });
         }
-        else {
+        else if attr.name.starts_with(':') { // Value of an argument of another custom tag, it is parsed when that tag is instantiated
             res_attibutes.push(attr.clone());
         }
+        else {
+            let value = attr.value.as_ref().map(|value| replace_attribute_references(value, |name| {
+                return tag.arguments.iter().position(|arg| arg == name).map(|i| get_attribute_text(&arguments[i]));
+            }));
+
+            res_attibutes.push(TagAttribute {
+                value,
+                ..attr.clone()
+            });
+        }
     }
 
     let mut res = Node {
@@ -212,12 +339,6 @@ fn instantiate_tag_inner(tag: &CustomTag, node: &Node, arguments: &Vec<Node>, co
 
     for c in &node.content {
         match c {
-            super::NodeContent::Character(c) => {
-                res.content.push(super::NodeContent::Character(c.clone()));
-            },
-            super::NodeContent::EscapedCharacter(c) => {
-                res.content.push(super::NodeContent::EscapedCharacter(c.clone()));
-            },
             super::NodeContent::Child(child_id) => {
                 let child = &node.children[*child_id];
 
@@ -239,6 +360,7 @@ fn instantiate_tag_inner(tag: &CustomTag, node: &Node, arguments: &Vec<Node>, co
                     res.children[*child_id] = new_child;
                 }
             },
+            other => res.content.push(other.clone()),
         }
     }
 
@@ -259,30 +381,318 @@ pub fn has_inner_param(tag: &CustomTag) -> bool {
     return false;
 }
 
-// Returns error if finds colon tags which name is NOT in the list 
-pub fn check_colon_tags(node: &Node, allowed_arguments: &Vec<String>) -> Result<(), ParseError> {
+/// Replaces the colon tags by the value of the variable with the same name, defined with -D. The value is inserted as text.
+/// Arguments of the custom tag shadow variables, and are not replaced.
+/// Colon tags that are neither variables nor arguments are left, `check_colon_tags` will report them.
+pub fn replace_variables(node: &mut Node, variables: &HashMap<String, String>, arguments: &Vec<String>) {
+    for attr in &mut node.attributes {
+        if attr.name.starts_with(':') {
+            continue; // Parsed later, like the content
+        }
+
+        if let Some(value) = &attr.value {
+            attr.value = Some(replace_attribute_references(value, |name| {
+                if arguments.iter().any(|arg| arg == name) {
+                    return None;
+                }
+
+                return variables.get(name).map(|value| escape_attribute_text(value));
+            }));
+        }
+    }
+
+    let mut children: Vec<Option<Node>> = std::mem::take(&mut node.children).into_iter().map(Some).collect();
+    let content = std::mem::take(&mut node.content);
+
+    for c in content {
+        match c {
+            super::NodeContent::Child(child_id) => {
+                let mut child = children[child_id].take().expect("Uuh?");
+
+                let value = if child.declaration_symbol == TagSymbol::COLON && child.auto_closing && !arguments.contains(&child.name) {
+                    variables.get(&child.name)
+                } 
+                else {
+                    None
+                };
+
+                match value {
+                    Some(value) => {
+                        if !value.is_empty() {
+                            node.content.push(super::NodeContent::Text((value.clone(), child.start_position.clone())));
+                        }
+                    },
+                    None => {
+                        replace_variables(&mut child, variables, arguments);
+                        node.content.push(super::NodeContent::Child(node.children.len()));
+                        node.children.push(child);
+                    },
+                }
+            },
+            other => node.content.push(other),
+        }
+    }
+}
+
+
+// Reports an error with `parser::add_error` for each colon tag which name is NOT in the list, in the content or in attribute values
+pub fn check_colon_tags(node: &Node, allowed_arguments: &Vec<String>, context: &parser::Context) {
+    check_attribute_references(node, allowed_arguments, context);
+
     for child in &node.children {
         if child.declaration_symbol == super::TagSymbol::COLON {
-            if allowed_arguments.contains(&child.name) {
-                // Ok, the argument exists!
+            if !allowed_arguments.contains(&child.name) {
+                report_unknown_colon_tag(&child.name, child.start_position.clone(), child.name.chars().count() + 2, allowed_arguments, context);
             }
-            else {
-                return Err(ParseError {
-                    message: format!("\
-Unknown parameter \"{}\" used. You may have forgotten to add it in the custom tag declaration. \
-If you meant to use a regular tag, remove the colon.", child.name),
-                    position: child.start_position.clone(),
-                    length: child.name.chars().count() + 2,
-                });
+        }
+        else {
+            check_colon_tags(child, allowed_arguments, context);
+        }
+    }
+}
+
+
+/// Same as `check_colon_tags`, for the colon tags in the attribute values of the node
+fn check_attribute_references(node: &Node, allowed_arguments: &Vec<String>, context: &parser::Context) {
+    for attr in &node.attributes {
+        if attr.name.starts_with(':') {
+            continue; // Checked when the value is parsed
+        }
+
+        if let (Some(value), Some(value_position)) = (&attr.value, &attr.value_position) {
+            for (start, length, name) in get_attribute_references(value) {
+                if !allowed_arguments.contains(&name) {
+                    let mut position = value_position.clone();
+                    position.absolute_position += start;
+                    position.line_character += start;
+
+                    report_unknown_colon_tag(&name, position, length, allowed_arguments, context);
+                }
             }
         }
-        else if !child.auto_closing {
-            check_colon_tags(child, allowed_arguments)?;
+    }
+}
+
+
+/// Reports an error for a colon tag that is neither an argument nor a variable. Helper for `check_colon_tags`
+fn report_unknown_colon_tag(name: &str, position: FilePosition, length: usize, allowed_arguments: &Vec<String>, context: &parser::Context) {
+    if allowed_arguments.is_empty() { // Not in a custom tag with arguments, it can only be a variable
+        parser::add_error(context, ParseError {
+            message: format!("Undefined variable \"{}\". Define it on the command line with -D {}=value.", name, name),
+            position,
+            length,
+        });
+    }
+    else {
+        parser::add_error(context, ParseError {
+            message: format!("\
+Unknown parameter or undefined variable \"{}\" used. You may have forgotten to add it in the custom tag declaration, \
+or to define it on the command line with -D {}=value. If you meant to use a regular tag, remove the colon.", name, name),
+            position,
+            length,
+        });
+    }
+}
+
+
+/// Finds the colon tags written in an attribute value, like in "#sec-<:id/>"
+///
+/// # Returns
+/// * for each one, its position in the value and its length (in characters), then its name
+///
+fn get_attribute_references(value: &str) -> Vec<(usize, usize, String)> {
+    let chars: Vec<char> = value.chars().collect();
+    let mut res = Vec::new();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i..].starts_with(&['<', ':']) {
+            i += 1;
+            continue;
+        }
+
+        // Spaces are allowed around the name, like in tags
+        let mut name_start = i + 2;
+        while name_start < chars.len() && chars[name_start].is_whitespace() {
+            name_start += 1;
+        }
+
+        let mut end = name_start;
+        while end < chars.len() && (chars[end].is_alphanumeric() || super::WORD_CHARS.contains(chars[end])) {
+            end += 1;
+        }
+        let name: String = chars[name_start..end].iter().collect();
+
+        while end < chars.len() && chars[end].is_whitespace() {
+            end += 1;
+        }
+
+        if !name.is_empty() && chars[end..].starts_with(&['/', '>']) {
+            res.push((i, end + 2 - i, name));
+            i = end + 2;
+        }
+        else {
+            i += 2;
         }
     }
 
-    return Ok(());
+    return res;
+}
+
+
+/// Replaces the colon tags of an attribute value by the text returned by `get_value`. The colon tags for which it returns None are kept
+fn replace_attribute_references(value: &str, get_value: impl Fn(&str) -> Option<String>) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut res = String::with_capacity(value.len());
+
+    let mut written = 0;
+    for (start, length, name) in get_attribute_references(value) {
+        if let Some(replacement) = get_value(&name) {
+            res.extend(&chars[written..start]);
+            res.push_str(&replacement);
+            written = start + length;
+        }
+    }
+    res.extend(&chars[written..]);
+
+    return res;
 }
 
 
+/// Returns the text of an argument, escaped to be written in an attribute value. Inner tags are ignored, like in `get_node_content_as_str`
+fn get_attribute_text(node: &Node) -> String {
+    let mut res = String::new();
 
+    for c in &node.content {
+        match c {
+            super::NodeContent::Text((text, _)) => res.push_str(&escape_attribute_text(text)),
+            super::NodeContent::EscapedCharacter((c, _)) => res.push_str(&escape_attribute_text(&c.to_string())),
+            super::NodeContent::Entity((entity, _)) => res.push_str(entity), // Already written in HTML
+            super::NodeContent::Ampersand((is_double, _)) => res.push_str(if *is_double { "&amp;&amp;" } else { "&amp;" }),
+            super::NodeContent::Child(_) => {},
+        }
+    }
+
+    // Arguments given as attributes get spaces after them, see `instantiate_all_custom_tags`
+    return String::from(res.trim());
+}
+
+
+/// Escapes the characters that can't be written as is in an attribute value
+fn escape_attribute_text(text: &str) -> String {
+    return text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;");
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{parse_snippet, with_context};
+
+    /// Checks the name of the tag defined by `definition`, and returns the error message if any, and the number of warnings
+    fn check_name(definition: &str, is_override: bool) -> (Option<String>, usize) {
+        let node = with_context(|context| parse_snippet(definition, context));
+        let (res, warning_count) = log::count_warnings(|| check_tag_name(&node, is_override));
+
+        return (res.err().map(|err| err.message), warning_count);
+    }
+
+    #[test]
+    fn element_names_need_override() {
+        let (error, _) = check_name("<!div>a</div>", false);
+        assert!(error.expect("should be refused").contains("has the name of an HTML element"));
+
+        assert_eq!(check_name("<!span>a</span>", true), (None, 0));
+        assert_eq!(check_name("<!BR>a</BR>", true), (None, 0)); // Void elements too, whatever the case
+    }
+
+    #[test]
+    fn override_on_a_new_name_warns() {
+        assert_eq!(check_name("<!note>a</note>", true), (None, 1));
+        assert_eq!(check_name("<!note>a</note>", false), (None, 0));
+    }
+
+    #[test]
+    fn invalid_tag_names_are_refused() {
+        for definition in ["<!my:tag>a</my:tag>", "<!2col>a</2col>", "<!é>a</é>"] {
+            let (error, _) = check_name(definition, false);
+            assert!(error.expect("should be refused").starts_with("Invalid custom tag name"), "{}", definition);
+        }
+        assert_eq!(check_name("<!my-tag_2>a</my-tag_2>", false), (None, 0));
+    }
+
+    #[test]
+    fn override_and_split_are_not_written_in_instances() {
+        let tag = with_context(|context| {
+            let definition = parse_snippet("<!span override split class=\"a\" :x>a</span>", context);
+            return get_custom_tag(definition, context.args, context).expect("should be valid");
+        });
+
+        assert!(tag.split);
+        let names: Vec<&str> = tag.content.attributes.iter().map(|attr| attr.name.as_str()).collect();
+        assert_eq!(names, vec!["class", ":x"]);
+    }
+
+    #[test]
+    fn operator_names_are_not_checked() {
+        // Operators are only used in math, so any word and element names are fine
+        with_context(|context| {
+            for definition in ["<?é>x</é>", "<?a>y</a>"] {
+                let node = parse_snippet(definition, context);
+                let (res, warning_count) = log::count_warnings(|| get_custom_tag(node, context.args, context));
+                assert!(res.is_ok() && warning_count == 0, "{}", definition);
+            }
+        });
+    }
+
+    #[test]
+    fn interpolated_attribute_values_are_escaped() {
+        let value = with_context(|context| {
+            let definition = parse_snippet("<!ref-link :title><a title=\"[<:title/>]\">x</a></ref-link>", context);
+            let tag = get_custom_tag(definition, context.args, context).expect("should be valid");
+
+            let argument = parse_snippet("<title>a \"b\" \\<c\\> x & y &copy;</title>", context);
+            let instance = instantiate_tag(&tag, vec![argument]);
+            return parser::get_attribute_value(&instance.children[0], "title").expect("should be there").map(String::from);
+        });
+
+        // Character references are already written in HTML, so they are kept
+        assert_eq!(value.as_deref(), Some("[a &quot;b&quot; &lt;c&gt; x &amp; y &copy;]"));
+    }
+
+    fn get_variables() -> HashMap<String, String> {
+        return HashMap::from([(String::from("client"), String::from("A&B <Co>")), (String::from("year"), String::from("2026"))]);
+    }
+
+    #[test]
+    fn variables_are_replaced_in_content_and_attributes() {
+        let node = with_context(|context| {
+            let mut node = parse_snippet("<p title=\"<:client/> <:year/>\">For <:client/>, <:year/></p>", context);
+            replace_variables(&mut node, &get_variables(), &Vec::new());
+            return node;
+        });
+
+        assert!(node.children.is_empty());
+        assert_eq!(parser::get_node_content_as_str(&node), "For A&B <Co>, 2026");
+        assert_eq!(parser::get_attribute_value(&node, "title"), Ok(Some("A&amp;B &lt;Co&gt; 2026")));
+    }
+
+    #[test]
+    fn arguments_shadow_variables_and_undefined_variables_are_errors() {
+        let (shadowed, errors) = with_context(|context| {
+            let mut node = parse_snippet("<p><:year/></p>", context);
+            replace_variables(&mut node, &get_variables(), &vec![String::from("year")]);
+            let shadowed: Vec<String> = node.children.iter().map(|child| child.name.clone()).collect();
+
+            let mut node = parse_snippet("<p>For <:client/> <:nope/></p>", context);
+            replace_variables(&mut node, &get_variables(), &Vec::new());
+            check_colon_tags(&node, &Vec::new(), context);
+            return (shadowed, parser::collect_errors(Ok(node), context).expect_err("should fail"));
+        });
+
+        assert_eq!(shadowed, vec!["year"]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("\"nope\""));
+        assert_eq!((errors[0].position.line, errors[0].position.line_character, errors[0].length), (0, 19, 6)); // From the colon, like the other errors about colon tags
+    }
+}
@@ -0,0 +1,358 @@
+use std::path::PathBuf;
+
+use crate::Context;
+use crate::parser::{Node, NodeContent, ParseError, TagSymbol};
+
+// Formats source files for the fmt subcommand: the content of tags that only contain tags is indented, and the attributes of each tag are put on its first line.
+// Everything else is written as it is in the source: comments, math, code blocks, and the content of custom tags and of tags that contain text
+
+
+/// One level of indentation
+const INDENT: &str = "    ";
+
+
+/// Returns the formatted source of a cow or cowx file, or all the errors found in it
+pub fn format_source(file_path: &PathBuf, chars: &Vec<char>, context: &Context) -> Result<String, Vec<ParseError>> {
+    let res = parse_all_tags(file_path, chars, context);
+    let tags = super::collect_errors(res, context)?;
+
+    let mut res = String::with_capacity(chars.len());
+    write_content(chars, 0, chars.len(), &tags.iter().collect(), 0, 0, &mut res);
+
+    // Exactly one line break at the end of the file
+    res.truncate(res.trim_end().len());
+    res.push('\n');
+
+    return Ok(res);
+}
+
+
+/// Parses the tags of the file one after the other: a document has only one, but a cowx file has one per definition
+fn parse_all_tags(file_path: &PathBuf, chars: &Vec<char>, context: &Context) -> Result<Vec<Node>, ParseError> {
+    let mut pos = super::get_start_of_file_position(file_path.clone());
+    let mut res = Vec::new();
+
+    loop {
+        // Fails at the end of the file
+        if super::advance_until_non_whitespace(chars, &mut pos).is_err() || pos.absolute_position >= chars.len() {
+            break;
+        }
+
+        res.push(super::parse_tag(chars, &mut pos, TagSymbol::NOTHING | TagSymbol::EXCLAMATION_MARK | TagSymbol::QUESTION_MARK, false, context)?);
+    }
+
+    return Ok(res);
+}
+
+
+/// Writes the source between `start` and `end`, with the tags it contains. Text is kept, but the lines are indented again.
+/// If there are no tags, the indentation of a line relative to the least indented one is kept, for the code written in tags
+///
+/// # Arguments
+/// * `children`: the nodes located between `start` and `end`, in the order of the source
+/// * `inner_level`: indentation of the lines
+/// * `closing_level`: indentation of the last line, if it only contains the closing tag
+///
+fn write_content(chars: &Vec<char>, start: usize, end: usize, children: &Vec<&Node>, inner_level: usize, closing_level: usize, res: &mut String) {
+    let mut content = String::new();
+    let mut line_starts: Vec<(usize, &[char])> = Vec::new(); // Where the lines start in `content`, and their indentation in the source
+    let mut ends_with_closing_line = false;
+
+    let keep_relative_indentation = children.is_empty();
+    let mut children = children.iter().peekable();
+    let mut i = start;
+
+    while i < end {
+        if let Some(child) = children.peek() {
+            let (child_start, child_end) = get_source_span(chars, child);
+            if child_start == i {
+                write_node(chars, child, inner_level, &mut content);
+                children.next();
+                i = child_end;
+                continue;
+            }
+        }
+
+        let c = chars[i];
+
+        if c == '\\' { // Don't look at the escaped character, it may be whitespace that must stay here
+            let escape_end = std::cmp::min(i + 2, end);
+            content.extend(&chars[i..escape_end]);
+            i = escape_end;
+        }
+        else if let Some(comment_end) = get_comment_end(chars, i, end) {
+            content.extend(&chars[i..comment_end]);
+            i = comment_end;
+        }
+        else if c.is_whitespace() {
+            let whitespace_end = chars[i..end].iter().position(|c| !c.is_whitespace()).map_or(end, |length| i + length);
+            let line_break_count = chars[i..whitespace_end].iter().filter(|c| **c == '\n').count();
+
+            if line_break_count == 0 {
+                content.extend(&chars[i..whitespace_end]);
+            }
+            else {
+                // The parser replaces whitespace by one space anyway, so trailing whitespace and indentation can be changed
+                for _ in 0..line_break_count {
+                    content.push('\n');
+                }
+
+                let indentation_start = i + chars[i..whitespace_end].iter().rposition(|c| *c == '\n').expect("Uuh?") + 1;
+                line_starts.push((content.len(), &chars[indentation_start..whitespace_end]));
+                ends_with_closing_line = whitespace_end == end;
+            }
+
+            i = whitespace_end;
+        }
+        else {
+            content.push(c);
+            i += 1;
+        }
+    }
+
+    let closing_line_count = if ends_with_closing_line { 1 } else { 0 };
+    let inner_line_count = line_starts.len() - closing_line_count;
+    let base_indentation = line_starts[..inner_line_count].iter().map(|(_, indentation)| indentation.len()).min().unwrap_or(0);
+
+    let mut written = 0;
+    for (line_id, (line_start, indentation)) in line_starts.iter().enumerate() {
+        res.push_str(&content[written..*line_start]);
+
+        if line_id < inner_line_count {
+            res.push_str(&INDENT.repeat(inner_level));
+
+            if keep_relative_indentation {
+                res.extend(&indentation[base_indentation..]);
+            }
+        }
+        else {
+            res.push_str(&INDENT.repeat(closing_level));
+        }
+
+        written = *line_start;
+    }
+    res.push_str(&content[written..]);
+}
+
+
+/// Writes a node: its opening tag is written again, and its content is indented one level more than `level`
+fn write_node(chars: &Vec<char>, node: &Node, level: usize, res: &mut String) {
+    let (start, end) = get_source_span(chars, node);
+
+    // Math and code blocks are kept as written
+    if is_code_block(chars, node) || is_math_block(chars, node) {
+        res.extend(&chars[start..end]);
+        return;
+    }
+
+    write_opening_tag(chars, node, res);
+
+    if node.auto_closing {
+        return;
+    }
+
+    // The content ends where the closing tag starts, and tag names can't contain "<"
+    let closing_start = start + chars[start..end].iter().rposition(|c| *c == '<').expect("Uuh?");
+    let inner_start = node.start_inner_position.absolute_position;
+
+    if is_content_kept(node) {
+        res.extend(&chars[inner_start..closing_start]);
+    }
+    else {
+        let children = node.content.iter().filter_map(|c| match c {
+            NodeContent::Child(id) => Some(&node.children[*id]),
+            _ => None,
+        }).collect();
+
+        write_content(chars, inner_start, closing_start, &children, level + 1, level, res);
+    }
+
+    res.push_str(&format!("</{}>", node.name));
+}
+
+
+/// Writes the opening tag of a node on one line, with one space before each attribute. Auto-closing tags always get a slash
+fn write_opening_tag(chars: &Vec<char>, node: &Node, res: &mut String) {
+    res.push('<');
+    res.push_str(get_symbol_text(node.declaration_symbol));
+    res.push_str(&node.name);
+
+    for attr in &node.attributes {
+        res.push(' ');
+        res.push_str(&attr.name);
+
+        if let (Some(value), Some(value_position)) = (&attr.value, &attr.value_position) {
+            // Comments are removed from the values of regular attributes, so read the value again from the source
+            let is_quoted = chars[value_position.absolute_position - 1] == '"';
+            let raw_value = if is_quoted {
+                super::read_until_quote(chars, &mut value_position.clone()).expect("Uuh?")
+            }
+            else {
+                value.clone()
+            };
+
+            res.push_str(&format!("=\"{}\"", raw_value));
+        }
+    }
+
+    if node.auto_closing {
+        res.push('/');
+    }
+
+    res.push('>');
+}
+
+
+/// Where the node is in the source, from its first character to the one after its last
+fn get_source_span(chars: &Vec<char>, node: &Node) -> (usize, usize) {
+    let start = node.start_position.absolute_position;
+    let end = start + node.source_length;
+
+    if is_code_block(chars, node) { // Starts at the backticks
+        return (start, end);
+    }
+    else if is_math_block(chars, node) { // Starts after the dollars, and maybe after a comment
+        let is_big = node.attributes.iter().any(|attr| attr.name == "class"); // See `parse_inner_tag`
+        let last_dollar = chars[..start].iter().rposition(|c| *c == '$').expect("Uuh?");
+        return (if is_big { last_dollar - 1 } else { last_dollar }, end);
+    }
+    else { // Starts after the "<"
+        return (start - 1, end);
+    }
+}
+
+
+/// Is it a code block written with backticks?
+fn is_code_block(chars: &Vec<char>, node: &Node) -> bool {
+    return (node.name == "code" || node.name == "pre") && chars[node.start_position.absolute_position] == '`';
+}
+
+
+/// Is it math written with dollars?
+fn is_math_block(chars: &Vec<char>, node: &Node) -> bool {
+    return node.name == "mathnode" && chars[node.start_position.absolute_position - 1] != '<';
+}
+
+
+/// Should the content of the tag be written exactly as in the source? Math, tags where whitespace matters,
+/// custom tags, whose content is evaluated or substituted, and tags that contain text
+fn is_content_kept(node: &Node) -> bool {
+    let is_custom_tag = node.declaration_symbol == TagSymbol::EXCLAMATION_MARK || node.declaration_symbol == TagSymbol::QUESTION_MARK;
+    let has_text = node.content.iter().any(|c| match c {
+        NodeContent::Text((text, _)) => !text.trim().is_empty(),
+        NodeContent::Child(_) => false,
+        _ => true,
+    });
+
+    return node.is_math || is_custom_tag || has_text || node.name == "pre" || node.name == "script" || node.name == "style";
+}
+
+
+/// If a comment starts at `i`, returns where it ends, but not after `end`. The line break after a "//" comment isn't part of it
+fn get_comment_end(chars: &Vec<char>, i: usize, end: usize) -> Option<usize> {
+    let rest = &chars[i..end];
+
+    if rest.starts_with(&['/', '/']) {
+        return Some(rest.iter().position(|c| *c == '\n').map_or(end, |length| i + length));
+    }
+    else if rest.starts_with(&['/', '*']) {
+        // Comments can be nested
+        let mut depth = 0;
+        let mut j = 0;
+        while j < rest.len() {
+            if rest[j..].starts_with(&['/', '*']) {
+                depth += 1;
+                j += 2;
+            }
+            else if rest[j..].starts_with(&['*', '/']) {
+                depth -= 1;
+                j += 2;
+
+                if depth == 0 {
+                    break;
+                }
+            }
+            else {
+                j += 1;
+            }
+        }
+
+        return Some(i + std::cmp::min(j, rest.len()));
+    }
+    else if rest.starts_with(&['<', '!', '-', '-']) {
+        let comment_length = (4..rest.len()).find(|j| rest[*j..].starts_with(&['-', '-', '>'])).map_or(rest.len(), |j| j + 3);
+        return Some(i + comment_length);
+    }
+    else {
+        return None;
+    }
+}
+
+
+/// The character written before the name of the tag
+fn get_symbol_text(symbol: TagSymbol) -> &'static str {
+    if symbol == TagSymbol::QUESTION_MARK { return "?"; }
+    else if symbol == TagSymbol::EXCLAMATION_MARK { return "!"; }
+    else if symbol == TagSymbol::COLON { return ":"; }
+    else if symbol == TagSymbol::PERCENTAGE { return "%"; }
+    else { return ""; }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+    use crate::test_util::with_context;
+
+    fn format(file_name: &str, source: &str) -> Result<String, Vec<ParseError>> {
+        return with_context(|context| format_source(&PathBuf::from(file_name), &source.chars().collect(), context));
+    }
+
+    #[test]
+    fn tags_are_indented_and_attributes_joined() {
+        let source = "<document>\n<head><title>T</title></head>\n<body>\n  <p   class=\"a\"\n id=\"b\">text <b>bold</b>\n  $x^2$\n</p>\n</body>\n</document>\n\n";
+        let expected = "<document>\n    <head><title>T</title></head>\n    <body>\n        <p class=\"a\" id=\"b\">text <b>bold</b>\n  $x^2$\n</p>\n    </body>\n</document>\n";
+
+        assert_eq!(format("doc.cow", source).expect("should format"), expected);
+    }
+
+    #[test]
+    fn content_of_custom_tags_is_kept() {
+        let source = std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("default/default.cowx")).unwrap();
+        let formatted = format("default.cowx", &source).expect("should format");
+
+        let get_evaluate_bodies = |text: &str| -> Vec<String> {
+            return text.split("<!evaluate").skip(1).map(|part| String::from(&part[..part.find("</evaluate>").unwrap()])).collect();
+        };
+        let bodies = get_evaluate_bodies(&source);
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(get_evaluate_bodies(&formatted), bodies);
+
+        assert!(formatted.contains("<!system :inner split>\n    %{\n        <:inner/>\n    }\n</system>"));
+    }
+
+    #[test]
+    fn code_blocks_are_kept() {
+        let source = "<document><body>\n``\n  code  <p>\n``\n</body></document>";
+        let formatted = format("doc.cow", source).expect("should format");
+
+        assert!(formatted.contains("``\n  code  <p>\n``"));
+    }
+
+    #[test]
+    fn formatting_is_idempotent() {
+        for file_name in ["test.cow", "test.cowx", "default/default.cowx", "docs/getting_started.cow"] {
+            let source = std::fs::read_to_string(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(file_name)).unwrap();
+            let formatted = format(file_name, &source).expect("should format");
+
+            assert_eq!(format(file_name, &formatted).expect("should format"), formatted, "{} changes when formatted twice", file_name);
+        }
+    }
+
+    #[test]
+    fn errors_are_returned() {
+        let errors = format("doc.cow", "<document><body><p>x</q></body></document>").expect_err("should fail");
+        assert!(errors[0].message.starts_with("Unexpected closing tag \"</q>\""));
+    }
+}
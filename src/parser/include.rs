@@ -0,0 +1,204 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::Context;
+use crate::parser::{Node, NodeContent, ParseError, TagSymbol};
+use crate::parser::conditions::{insert_content, is_directive};
+
+// Includes other files: <!include src="chapters/intro.cow"/>
+// The content of the body of the included file replaces the include tag, right after the file is parsed
+
+
+/// Replaces the include tags inside the node by the body of the files they refer to.
+/// Errors are remembered with `add_error`, and the include tags that failed are removed.
+///
+/// # Arguments
+/// * `including_files`: the file that contains the node, then the files that include it. Used to detect include cycles
+///
+pub fn resolve_includes(node: &mut Node, including_files: &mut Vec<PathBuf>, context: &Context) {
+    let mut children: Vec<Option<Node>> = std::mem::take(&mut node.children).into_iter().map(Some).collect();
+    let content = std::mem::take(&mut node.content);
+
+    for c in content {
+        match c {
+            NodeContent::Child(child_id) => {
+                let mut child = children[child_id].take().expect("Uuh?");
+
+                if is_directive(&child, "include") {
+                    match include_file(&child, including_files, context) {
+                        Ok(body) => insert_content(node, body),
+                        Err(err) => super::add_error(context, err),
+                    }
+                }
+                else {
+                    resolve_includes(&mut child, including_files, context);
+
+                    node.content.push(NodeContent::Child(node.children.len()));
+                    node.children.push(child);
+                }
+            },
+            other => node.content.push(other),
+        }
+    }
+}
+
+
+/// Returns the path used to compare files, to find include cycles
+pub fn get_include_path(path: &PathBuf) -> PathBuf {
+    return fs::canonicalize(path).unwrap_or(path.clone());
+}
+
+
+/// Reads and parses the file of an include tag, and returns its body, where includes are resolved
+fn include_file(include_node: &Node, including_files: &mut Vec<PathBuf>, context: &Context) -> Result<Node, ParseError> {
+    let tag_length = include_node.name.len() + 2;
+
+    let src = match crate::parser::get_attribute_value(include_node, "src") {
+        Ok(Some(src)) => src,
+        _ => {
+            return Err(ParseError {
+                message: String::from("An include tag needs a src attribute with the path of the file, like this: <!include src=\"chapter.cow\"/>."),
+                position: include_node.start_position.clone(),
+                length: tag_length,
+            });
+        },
+    };
+
+    if !include_node.content.is_empty() {
+        return Err(ParseError {
+            message: format!("An include tag can't have content. Write it like this: <!include src=\"{}\"/>.", src),
+            position: include_node.start_position.clone(),
+            length: tag_length,
+        });
+    }
+
+    // Relative to the file that contains the tag
+    let path = match include_node.start_position.file_path.parent() {
+        Some(parent) => parent.join(src),
+        None => PathBuf::from(src),
+    };
+
+    let include_path = get_include_path(&path);
+    if let Some(cycle_start) = including_files.iter().position(|file| *file == include_path) {
+        let cycle: Vec<String> = including_files[cycle_start..].iter().chain([&include_path]).map(|file| format!("\"{}\"", file.display())).collect();
+
+        return Err(ParseError {
+            message: format!("Include cycle: {}. A file can't include itself, even through other files.", cycle.join(" includes ")),
+            position: include_node.start_position.clone(),
+            length: tag_length,
+        });
+    }
+
+    context.dependencies.borrow_mut().push(path.clone());
+
+    let file_content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(ParseError {
+                message: format!("Could not read the included file \"{}\". ({}) The path is relative to the file that includes it.", path.display(), err),
+                position: include_node.start_position.clone(),
+                length: tag_length,
+            });
+        },
+    };
+
+    let root = super::parse_tag(&file_content.chars().collect(), &mut super::get_start_of_file_position(path.clone()), TagSymbol::NOTHING, false, context)?;
+
+    let mut body = if root.name == "body" {
+        root
+    }
+    else {
+        match root.children.into_iter().find(|child| child.name == "body") {
+            Some(body) => body,
+            None => {
+                return Err(ParseError {
+                    message: format!("The included file \"{}\" has no body. Put its content inside <body></body>.", path.display()),
+                    position: include_node.start_position.clone(),
+                    length: tag_length,
+                });
+            },
+        }
+    };
+
+    including_files.push(include_path);
+    resolve_includes(&mut body, including_files, context);
+    including_files.pop();
+
+    return Ok(body);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{get_node_content_as_str, get_start_of_file_position};
+    use crate::test_util::{with_context, write_files};
+
+    /// Writes the files, parses the first one and resolves its includes, then calls `f` with the result and the errors
+    fn with_included<T>(files: &[(&str, &str)], f: impl FnOnce(Node, Vec<ParseError>) -> T) -> T {
+        let dir = write_files(files);
+        let path = dir.join(files[0].0);
+
+        let res = with_context(|context| {
+            let chars = files[0].1.chars().collect();
+            let mut node = crate::parser::parse_tag(&chars, &mut get_start_of_file_position(path.clone()), TagSymbol::NOTHING, false, context).expect("should parse");
+            resolve_includes(&mut node, &mut vec![get_include_path(&path)], context);
+            return f(node, context.errors.take());
+        });
+
+        let _ = fs::remove_dir_all(&dir);
+        return res;
+    }
+
+    #[test]
+    fn body_of_included_file_replaces_the_tag() {
+        with_included(&[("doc.cow", "<p>a <!include src=\"inc/b.cow\"/> c</p>"), ("inc/b.cow", "<document><body>b</body></document>")], |node, errors| {
+            assert_eq!(get_node_content_as_str(&node), "a b c");
+            assert!(errors.is_empty());
+        });
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        with_included(&[("doc.cow", "<p><!include src=\"a.cow\"/></p>"), ("a.cow", "<body><!include src=\"doc.cow\"/></body>")], |_, errors| {
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].message.starts_with("Include cycle"));
+        });
+    }
+
+    #[test]
+    fn included_rows_are_split() {
+        // The positions of the rows are in two files
+        with_included(&[("doc.cow", "<rows><!include src=\"inc/rows.cow\"/></rows>"), ("inc/rows.cow", "<body>a & b && c & d</body>")], |mut node, _| {
+            crate::parser::split_node(&mut node);
+
+            assert_eq!(node.children.len(), 2);
+            assert!(node.children.iter().all(|row| row.name == "double-amp-split" && row.children.len() == 2));
+        });
+    }
+
+    #[test]
+    fn file_included_twice_in_rows() {
+        // The second row starts after the end of the first one in the included file
+        with_included(&[("doc.cow", "<rows><!include src=\"row.cow\"/> && <!include src=\"row.cow\"/></rows>"), ("row.cow", "<body>a && b</body>")], |mut node, _| {
+            crate::parser::split_node(&mut node);
+
+            assert_eq!(node.children.len(), 4);
+        });
+    }
+
+    #[test]
+    fn source_length_across_files_is_zero() {
+        let mut start = get_start_of_file_position(PathBuf::from("doc.cow"));
+        start.absolute_position = 30;
+        let mut end = get_start_of_file_position(PathBuf::from("inc/rows.cow"));
+        end.absolute_position = 10;
+
+        assert_eq!(crate::parser::get_source_length(&start, &end), 0);
+
+        // The same file included twice
+        end.file_path = start.file_path.clone();
+        assert_eq!(crate::parser::get_source_length(&start, &end), 0);
+        assert_eq!(crate::parser::get_source_length(&end, &start), 20);
+    }
+}
@@ -1,7 +1,16 @@
-use super::{FilePosition, Node, NodeContent, ParseError, Context, TagSymbol};
+use super::{FilePosition, Node, NodeContent, ParseError, Context, TagSymbol, RunOffset};
 use super::custom::CustomTag;
 
 
+/// The content of a node, with one element per character, to look at the characters one by one. See `get_math_items`
+enum MathItem<'a> {
+    Character((char, RunOffset<'a>)), // The position is only built when needed, see `get_run_position`
+    EscapedCharacter(&'a (char, FilePosition)),
+    Entity(&'a (String, FilePosition)),
+    Child(usize),
+}
+
+
 struct PartialNode {
     children: Vec<Node>,
     content: Vec<super::NodeContent>,
@@ -30,6 +39,7 @@ enum MathToken<'a> {
     Operator(&'a CustomTag),
     Other((char, FilePosition)),
     EscapedCharacter((char, FilePosition)),
+    Entity((String, FilePosition)),
     Child(usize),
     OpeningBrace,
     OpeningVisibleBrace(bool),
@@ -56,6 +66,7 @@ struct Alias<'a> {
 
 
 /// Create math! Called after tags are parsed. Will replace the provided Node's contents by math.
+/// Errors are remembered with `add_error`, and parsing continues after them to find the next ones. In this case the node is left empty.
 /// 
 /// # Arguments
 /// * `node`: A node. It's children are fully math-parsed, but not it's inner text
@@ -67,32 +78,53 @@ pub fn parse_math(node: &mut Node, context: &Context) -> Result<(), ParseError>
     let mut pos = 0;
 
     // Remove children from node to take ownership
-    let raw_children = std::mem::replace(&mut node.children, vec![]);
+    let raw_children = std::mem::take(&mut node.children);
     let mut children = raw_children.into_iter().map(|el| { PotentialChild::Some(el) }).collect();
 
-    let (res, _) = parse_math_part(node, &mut children, &mut pos, context, MathStopType::MathEnd)?;
+    let content = get_math_items(&node.content);
+
+    let mut failed = false;
 
-    // Replace node's contents
-    node.children = res.children;
-    node.content = res.content;
+    loop {
+        let start_pos = pos;
+
+        match parse_math_part(&content, &mut children, &mut pos, context, MathStopType::MathEnd) {
+            Ok((res, _)) => {
+                // Replace node's contents
+                if failed { // What was parsed after the error is incomplete, and won't be used
+                    node.children = vec![];
+                    node.content = vec![];
+                }
+                else {
+                    node.children = res.children;
+                    node.content = res.content;
+                }
 
-    return Ok(()); 
+                return Ok(()); 
+            },
+            Err(err) => {
+                super::add_error(context, err);
+                failed = true;
+                pos = usize::max(pos, start_pos + 1); // Continue after the error
+            },
+        }
+    }
 }
 
 
 /// Sub-function of parse_math. `pos` is the position in the node's content array
-fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &mut usize, context: &Context, how_to_stop: MathStopType) 
+fn parse_math_part(content: &Vec<MathItem>, children: &mut Vec<PotentialChild>, index: &mut usize, context: &Context, how_to_stop: MathStopType) 
     -> Result<(PartialNode, MathParseInfo), ParseError> {
-    let mut res: Vec<NodeContent> = Vec::with_capacity(node.content.len());
+    let mut res: Vec<NodeContent> = Vec::with_capacity(content.len());
     let mut res_children: Vec<Node> = Vec::with_capacity(5);
 
     let mut got_one_thing = false;
 
     loop {
         // Check for end of math
-        if *index >= node.content.len() {
+        if *index >= content.len() {
             if how_to_stop != MathStopType::MathEnd {
-                let position = get_file_pos_of_node_char(node, *index);
+                let position = get_file_pos_of_node_char(content, *index);
                 
                 return Err(ParseError {
                     message: String::from("Unexpected end of math or closing tag."),
@@ -105,7 +137,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
             }
         }
 
-        let next_token = match_next_thing_in_math(node, index, &children, context)?;
+        let next_token = match_next_thing_in_math(content, index, children, context)?;
 
         match next_token {
             MathToken::Alias(alias) => {
@@ -113,7 +145,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
 
                 match tag {
                     Some(tag) => {
-                        let (file_pos, _) = get_file_pos_of_node_content(node, &children, *index);
+                        let (file_pos, _) = get_file_pos_of_node_content(content, children, *index);
 
                         let alias_len = alias.alias.chars().count();
                         *index += alias_len;
@@ -128,7 +160,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                             }
 
                             // Nothing before infix operator
-                            if res.len() == 0 {
+                            if res.is_empty() {
                                 return Err(ParseError { 
                                     message: format!("Expected something before \"{}\", because it's an infix operator. You should write {{left}}{}{{right}} instead of {}{{left}}{{right}}", alias.alias, alias.alias, alias.alias), 
                                     position: file_pos, length: alias_len 
@@ -137,12 +169,14 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
 
                             // Gt the last element of the nodes's content
                             let left = match res.pop().unwrap() {
-                                NodeContent::Character(c) |
-                                NodeContent::EscapedCharacter(c) => Node { 
+                                NodeContent::Child(_) => {
+                                    res_children.pop().unwrap()
+                                },
+                                character => Node { 
                                     name: String::from("div"), 
                                     attributes: vec![], 
                                     children: vec![], 
-                                    content: vec![NodeContent::Character(c)], 
+                                    content: vec![character], 
                                     auto_closing: false, 
                                     is_math: true,
                                     declaration_symbol: TagSymbol::NOTHING, 
@@ -150,23 +184,20 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                                     start_inner_position: file_pos, 
                                     source_length: 1 
                                 },
-                                NodeContent::Child(_) => {
-                                    res_children.pop().unwrap()
-                                },
                             };
 
-                            if *index >= node.content.len() {
-                                let (position, _) = get_file_pos_of_node_content(node, children, node.content.len() - 1);
+                            if *index >= content.len() {
+                                let (position, _) = get_file_pos_of_node_content(content, children, content.len() - 1);
                                 return Err(ParseError { 
                                     message: format!("Expected something after \"{}\" because it's an infix operator.", alias.alias), 
                                     position, length: 1,
                                 });
                             }
 
-                            let (right, info) = parse_math_subgroup(node, children, index, context, MathStopType::OneThing)?;
+                            let (right, info) = parse_math_subgroup(content, children, index, context, MathStopType::OneThing)?;
 
                             if info.got_nothing {
-                                let (position, _) = get_file_pos_of_node_content(node, children, *index - 1);
+                                let (position, _) = get_file_pos_of_node_content(content, children, *index - 1);
                                 return Err(ParseError { 
                                     message: format!("Expected something after \"{}\" because it's an infix operator.", alias.alias), 
                                     position, length: 1,
@@ -179,18 +210,18 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                         else {
                             for i in 0..tag.arguments.len() {
                                 // Reached the end: not enough arguments
-                                if *index >= node.content.len() {
-                                    let (position, _) = get_file_pos_of_node_content(node, children, node.content.len() - 1);
+                                if *index >= content.len() {
+                                    let (position, _) = get_file_pos_of_node_content(content, children, content.len() - 1);
                                     return Err(ParseError { 
                                         message: format!("Expected something here. \"{}\" expects {} arguments, and you provided only {}", alias.alias, tag.arguments.len(), i), 
                                         position, length: 1,
                                     });
                                 }
 
-                                let (child, info) = parse_math_subgroup(node, children, index, context, MathStopType::OneThing)?;
+                                let (child, info) = parse_math_subgroup(content, children, index, context, MathStopType::OneThing)?;
 
                                 if info.got_nothing {
-                                    let (position, _) = get_file_pos_of_node_content(node, children, *index - 1);
+                                    let (position, _) = get_file_pos_of_node_content(content, children, *index - 1);
                                     return Err(ParseError { 
                                         message: format!("Expected something here. \"{}\" expects {} arguments, and you provided only {}", alias.alias, tag.arguments.len(), i), 
                                         position, length: 1,
@@ -209,7 +240,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                         got_one_thing = true;
                     },
                     None => {
-                        let (position, _) = get_file_pos_of_node_content(node, children, *index);
+                        let (position, _) = get_file_pos_of_node_content(content, children, *index);
                         return Err(ParseError { 
                             message: format!("Operator alias \"{}\" found, but corresponding tag \"{}\" not found. This is probably because you have modified default.cowx.", alias.alias, alias.tag_name), 
                             position, length: alias.alias.len()
@@ -220,19 +251,19 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
             MathToken::Operator(op) => {
                 let mut arguments = Vec::with_capacity(op.arguments.len());
                 for i in 0..op.arguments.len() {
-                    if *index >= node.content.len() {
-                        let (position, _) = get_file_pos_of_node_content(node, children, node.content.len() - 1);
+                    if *index >= content.len() {
+                        let (position, _) = get_file_pos_of_node_content(content, children, content.len() - 1);
                         return Err(ParseError { 
                             message: format!("Expected something here. \"{}\" expects {} arguments, and you provided only {}", op.content.name, op.arguments.len(), i), 
                             position, length: 1,
                         });
                     }
 
-                    let (child, info) = parse_math_subgroup(node, children, index, context, MathStopType::OneThing)?;
+                    let (child, info) = parse_math_subgroup(content, children, index, context, MathStopType::OneThing)?;
                     arguments.push(child);
 
                     if info.got_nothing {
-                        let (position, _) = get_file_pos_of_node_content(node, children, *index - 1);
+                        let (position, _) = get_file_pos_of_node_content(content, children, *index - 1);
                         return Err(ParseError { 
                             message: format!("Expected something here. \"{}\" expects {} arguments, and you provided only {}", op.content.name, op.arguments.len(), i), 
                             position, length: 1,
@@ -248,7 +279,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                 got_one_thing = true;
             },
             MathToken::OpeningBrace => { // Sub group. Make a recursive call
-                let (child, _) = parse_math_subgroup(node, children, index, context, MathStopType::Brace)?;
+                let (child, _) = parse_math_subgroup(content, children, index, context, MathStopType::Brace)?;
 
                 let new_child_id = res_children.len();
                 res_children.push(child);
@@ -257,7 +288,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
             },
             MathToken::ClosingBrace => {
                 if how_to_stop != MathStopType::Brace {
-                    return Err(report_stop_error(node, how_to_stop, &next_token, *index));
+                    return Err(report_stop_error(content, how_to_stop, &next_token, *index));
                 }
 
                 break;
@@ -278,7 +309,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                     None => {
                         return Err(ParseError {
                             message: format!("Operator \"{}\" not found! This may be because you modified default.cowx.", op_name),
-                            position: get_file_pos_of_node_char(node, *index - 1),
+                            position: get_file_pos_of_node_char(content, *index - 1),
                             length: 1,
                         });
                     }
@@ -289,7 +320,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                 res_children.push(super::custom::instantiate_tag(operator, vec![]));
 
                 if !compare_math_token_and_math_stop(&next_token, &how_to_stop) {
-                    return Err(report_stop_error(node, how_to_stop, &next_token, *index));
+                    return Err(report_stop_error(content, how_to_stop, &next_token, *index));
                 }
 
                 break;
@@ -310,7 +341,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                     None => {
                         return Err(ParseError {
                             message: format!("Operator \"{}\" not found! This may be because you modified default.cowx.", op_name),
-                            position: get_file_pos_of_node_char(node, *index - 1),
+                            position: get_file_pos_of_node_char(content, *index - 1),
                             length: 1,
                         });
                     }
@@ -323,7 +354,7 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                     _ => unreachable!(),
                 };
 
-                let (mut child, _) = parse_math_subgroup(node, children, index, context, stop_type)?;
+                let (mut child, _) = parse_math_subgroup(content, children, index, context, stop_type)?;
 
                 // Add a parenthesis at th beginning of the child
                 child.content.insert(0, NodeContent::Child(child.children.len()));
@@ -335,20 +366,20 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                 got_one_thing = true;
             },
             MathToken::Other(('§', _)) => {
-                let (letter_to_convert, letter_position) = match &node.content[*index] {
-                    NodeContent::Character(l) => l.clone(),
-                    NodeContent::EscapedCharacter(l) => {
-                        let (position, length) = get_file_pos_of_node_content(node, children, *index);
+                let (letter_to_convert, letter_position) = match &content[*index] {
+                    MathItem::Character((c, offset)) => (*c, super::get_run_position(offset)),
+                    MathItem::EscapedCharacter(l) => {
+                        let (position, length) = get_file_pos_of_node_content(content, children, *index);
                         crate::log::warning_position(
                             "Escaped character after \"§\". Consider removing the backslash.", 
                             &position, length
                         );
-                        l.clone()
+                        (*l).clone()
                     },
-                    NodeContent::Child(_) => {
-                        let (position, length) = get_file_pos_of_node_content(node, children, *index);
+                    MathItem::Entity(_) | MathItem::Child(_) => {
+                        let (position, length) = get_file_pos_of_node_content(content, children, *index);
                         return Err(ParseError {
-                            message: format!("Expected a character after \"§\", found a tag."),
+                            message: "Expected a letter after \"§\".".to_string(),
                             position, length
                         });
                     },
@@ -357,12 +388,12 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                 let greek_letter = letter_to_greek(letter_to_convert);
                 match greek_letter {
                     Some(l) => {
-                        res.push(NodeContent::Character((l, letter_position)));
+                        res.push(NodeContent::Text((String::from(l), letter_position)));
                         *index += 1;
                         got_one_thing = true;
                     },
                     None => {
-                        let (position, length) = get_file_pos_of_node_content(node, children, *index);
+                        let (position, length) = get_file_pos_of_node_content(content, children, *index);
                         return Err(ParseError {
                             message: format!("Character \"{}\" after \"§\" does not correspond to a greek letter. Only a-z, A-Z are accepted, except for q, Q, w and W", letter_to_convert),
                             position, length
@@ -375,12 +406,16 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
                     // Ignore whitespace!
                 }
                 else { // A normal character
-                    res.push(NodeContent::Character((c, file_position)));
+                    res.push(NodeContent::Text((String::from(c), file_position)));
                     got_one_thing = true;
                 }
             },
-            MathToken::EscapedCharacter(c) => {
-                res.push(NodeContent::Character(c));
+            MathToken::EscapedCharacter((c, file_position)) => {
+                res.push(NodeContent::Text((String::from(c), file_position)));
+                got_one_thing = true;
+            },
+            MathToken::Entity(entity) => {
+                res.push(NodeContent::Entity(entity));
                 got_one_thing = true;
             },
             MathToken::Child(c) => { // A child, just push it as a normal NodeContent
@@ -420,17 +455,37 @@ fn parse_math_part(node: &mut Node, children: &mut Vec<PotentialChild>, index: &
 }
 
 
+/// Splits the text runs of a node's content into characters
+fn get_math_items(content: &Vec<NodeContent>) -> Vec<MathItem<'_>> {
+    let mut res = Vec::with_capacity(content.len());
+
+    for el in content {
+        match el {
+            NodeContent::Text((text, start)) => {
+                res.extend(super::get_run_offsets(text, start).map(MathItem::Character));
+            },
+            NodeContent::EscapedCharacter(c) => res.push(MathItem::EscapedCharacter(c)),
+            NodeContent::Entity(e) => res.push(MathItem::Entity(e)),
+            NodeContent::Ampersand((is_double, start)) => { // Not split, see `split_all_ampersands`
+                let text = if *is_double { "&&" } else { "&" };
+                res.extend(super::get_run_offsets(text, start).map(MathItem::Character));
+            },
+            NodeContent::Child(c) => res.push(MathItem::Child(*c)),
+        }
+    }
+
+    return res;
+}
+
+
 /// Parses the math on a whole document
 pub fn parse_all_math(node: &mut Node, root_is_math: bool, context: &Context) -> Result<(), ParseError> {
     let this_tag_is_math = root_is_math || node.is_math;
 
     // Parse math on children
     for c in &node.content {
-        match c {
-            NodeContent::Child(c) => {
-                parse_all_math(&mut node.children[*c], this_tag_is_math, context)?;
-            },
-            _ => {}
+        if let NodeContent::Child(c) = c {
+            parse_all_math(&mut node.children[*c], this_tag_is_math, context)?;
         }
     }
     
@@ -445,30 +500,30 @@ pub fn parse_all_math(node: &mut Node, root_is_math: bool, context: &Context) ->
 
 /// Another helper for `parse_math_part`.
 /// Advances `index` past the next found thing
-fn match_next_thing_in_math<'a>(node: &mut Node, index: &mut usize, children: &Vec<PotentialChild>, context: &'a Context) -> Result<MathToken<'a>, ParseError> {
+fn match_next_thing_in_math<'a>(content: &Vec<MathItem>, index: &mut usize, children: &Vec<PotentialChild>, context: &'a Context) -> Result<MathToken<'a>, ParseError> {
     // See if there is an alias (if necessary)
-    let alias = if context.ignore_aliases { None } else { check_for_alias(&node.content, *index, context) };
+    let alias = if context.ignore_aliases { None } else { check_for_alias(content, *index, context) };
 
     match alias {
         Some(alias) => return Ok(MathToken::Alias(alias)),
         None => {
-            let current = &node.content[*index];
+            let current = &content[*index];
             
             // Check for characters after current, if they exists
             // Ys, this code is ugly
 
-            let after = if node.content.len() >= 1 && *index < node.content.len() - 1 {
-                match node.content[*index + 1] {
-                    NodeContent::Character((c, _)) => c,
+            let after = if !content.is_empty() && *index < content.len() - 1 {
+                match content[*index + 1] {
+                    MathItem::Character((c, _)) => c,
                     _ => '\0'
                 }
             } else {
                 '\0'
             };
             
-            let after_after = if node.content.len() >= 2 && *index < node.content.len() - 2 {
-                match node.content[*index + 2] {
-                    NodeContent::Character((c, _)) => c,
+            let after_after = if content.len() >= 2 && *index < content.len() - 2 {
+                match content[*index + 2] {
+                    MathItem::Character((c, _)) => c,
                     _ => '\0'
                 }
             } else {
@@ -478,7 +533,7 @@ fn match_next_thing_in_math<'a>(node: &mut Node, index: &mut usize, children: &V
             // Try to find out what is the next thing
 
             match &current {
-                NodeContent::Character((c, pos)) => {
+                MathItem::Character((c, offset)) => {
                     if      *c == '{' {
                         *index += 1;
                         return Ok(MathToken::OpeningBrace);
@@ -493,7 +548,7 @@ fn match_next_thing_in_math<'a>(node: &mut Node, index: &mut usize, children: &V
 You used \"!{\". The exclamation mark means \"this brace means closing instead of opening\". \
 Since this one isn't visible, it makes no sense. \
 You should either use \"}\", \"!%{\", or \"! {\"."),
-                            position: pos.clone(),
+                            position: super::get_run_position(offset),
                             length: 2,
                         });
                     }
@@ -503,7 +558,7 @@ You should either use \"}\", \"!%{\", or \"! {\"."),
 You used \"!}\". The exclamation mark means \"this brace means closing instead of opening\". \
 Since this one isn't visible, it makes no sense. \
 You should either use \"{\", \"!%}\", or \"! }\"."),
-                            position: pos.clone(),
+                            position: super::get_run_position(offset),
                             length: 2,
                         });
                     }
@@ -557,19 +612,23 @@ You should either use \"{\", \"!%}\", or \"! }\"."),
                     }
                     else if *c == '?' {
                         *index += 1;
-                        let op = expect_operator(node, &children, index, context)?;
+                        let op = expect_operator(content, children, index, context)?;
                         return Ok(MathToken::Operator(op));
                     }
                     else { // Any other character
                         *index += 1;
-                        return Ok(MathToken::Other((*c, pos.clone())));
+                        return Ok(MathToken::Other((*c, super::get_run_position(offset))));
                     }
                 },
-                NodeContent::EscapedCharacter(c) => {
+                MathItem::EscapedCharacter(c) => {
                     *index += 1;
-                    return Ok(MathToken::EscapedCharacter(c.clone()));
+                    return Ok(MathToken::EscapedCharacter((*c).clone()));
                 },
-                NodeContent::Child(c) => {
+                MathItem::Entity(e) => {
+                    *index += 1;
+                    return Ok(MathToken::Entity((*e).clone()));
+                },
+                MathItem::Child(c) => {
                     *index += 1;
                     return Ok(MathToken::Child(*c));
                 },
@@ -580,15 +639,15 @@ You should either use \"{\", \"!%}\", or \"! }\"."),
 
 
 /// Tries to read an operator AFTER the question mark
-fn expect_operator<'a>(node: &Node, children: &Vec<PotentialChild>, pos: &mut usize, context: &'a Context) -> Result<&'a CustomTag, ParseError> {
+fn expect_operator<'a>(content: &Vec<MathItem>, children: &Vec<PotentialChild>, pos: &mut usize, context: &'a Context) -> Result<&'a CustomTag, ParseError> {
     let mut word = String::with_capacity(15);
     let start_pos = *pos - 1;
 
-    while *pos < node.content.len() {
-        let el = &node.content[*pos];
+    while *pos < content.len() {
+        let el = &content[*pos];
 
         match *el {
-            NodeContent::Character((c, _)) => {
+            MathItem::Character((c, _)) => {
                 if super::WORD_CHARS.contains(c) || c.is_alphabetic() {
                     word.push(c);
                 }
@@ -596,10 +655,10 @@ fn expect_operator<'a>(node: &Node, children: &Vec<PotentialChild>, pos: &mut us
                     break;
                 }
             },
-            NodeContent::EscapedCharacter(_) => {
+            MathItem::EscapedCharacter(_) | MathItem::Entity(_) => {
                 break;
             },
-            NodeContent::Child(_) => {
+            MathItem::Child(_) => {
                 break;
             },
         }
@@ -607,16 +666,16 @@ fn expect_operator<'a>(node: &Node, children: &Vec<PotentialChild>, pos: &mut us
         *pos += 1;
     }
 
-    if word == "" {
-        let (position, _) = get_file_pos_of_node_content(node, children, start_pos);
+    if word.is_empty() {
+        let (position, _) = get_file_pos_of_node_content(content, children, start_pos);
         return Err(ParseError { 
-            message: format!("Nothing found after \"?\". Question marks are used for operators. If you wanted to add a question mark in math, put a backslash before: \"\\?\""), 
+            message: "Nothing found after \"?\". Question marks are used for operators. If you wanted to add a question mark in math, put a backslash before: \"\\?\"".to_string(), 
             position, 
             length: word.len() + 1,
         });
     }
 
-    let (position, _) = get_file_pos_of_node_content(node, children, start_pos);
+    let (position, _) = get_file_pos_of_node_content(content, children, start_pos);
     match context.custom_tags.get(&word) {
         Some(op) => {
             if !op.is_math {
@@ -642,11 +701,11 @@ fn expect_operator<'a>(node: &Node, children: &Vec<PotentialChild>, pos: &mut us
 
 
 /// Returns the proper error if a tag is present instead of a character
-fn expect_character(node: &Node, children: &Vec<PotentialChild>, id: usize) -> Result<char, ParseError> {
-    match node.content[id] {
-        NodeContent::Character((c, _)) => return Ok(c),
+fn expect_character(content: &Vec<MathItem>, children: &Vec<PotentialChild>, id: usize) -> Result<char, ParseError> {
+    match content[id] {
+        MathItem::Character((c, _)) => return Ok(c),
         _ => {
-            let (position, length) = get_file_pos_of_node_content(node, children, id);
+            let (position, length) = get_file_pos_of_node_content(content, children, id);
             return Err(ParseError { message: String::from("Didn't expected a tag here."), position, length });
         }
     }
@@ -658,17 +717,20 @@ fn expect_character(node: &Node, children: &Vec<PotentialChild>, id: usize) -> R
 /// # Arguments
 /// * `children`: the children of the node (in case they are separated)
 /// * `content_id`: the position of the desired content in the content array of the node.
-fn get_file_pos_of_node_content(node: &Node, children: &Vec<PotentialChild>, content_id: usize) -> (FilePosition, usize) {
-    let id = std::cmp::min(content_id, node.content.len() - 1);
+fn get_file_pos_of_node_content(content: &Vec<MathItem>, children: &Vec<PotentialChild>, content_id: usize) -> (FilePosition, usize) {
+    let id = std::cmp::min(content_id, content.len() - 1);
 
-    match &node.content[id] {
-        NodeContent::Character((_, pos)) => {
-            return (pos.clone(), 1);
+    match &content[id] {
+        MathItem::Character((_, offset)) => {
+            return (super::get_run_position(offset), 1);
         },
-        NodeContent::EscapedCharacter((_, pos)) => {
+        MathItem::EscapedCharacter((_, pos)) => {
             return (pos.clone(), 2);
         },
-        NodeContent::Child(c) => {
+        MathItem::Entity((entity, pos)) => {
+            return (pos.clone(), entity.chars().count());
+        },
+        MathItem::Child(c) => {
             match &children[*c] {
                 PotentialChild::Some(child) => {
                     return (child.start_position.clone(), child.source_length);
@@ -683,29 +745,29 @@ fn get_file_pos_of_node_content(node: &Node, children: &Vec<PotentialChild>, con
 
 
 // Same as `get_file_pos_of_node_content`, but panics if a child is found and returns only a position
-fn get_file_pos_of_node_char(node: &Node, content_id: usize) -> FilePosition {
-    let id = std::cmp::min(content_id, node.content.len() - 1);
+fn get_file_pos_of_node_char(content: &Vec<MathItem>, content_id: usize) -> FilePosition {
+    let id = std::cmp::min(content_id, content.len() - 1);
 
-    match &node.content[id] {
-        NodeContent::Character((_, pos)) => {
-            return pos.clone();
+    match &content[id] {
+        MathItem::Character((_, offset)) => {
+            return super::get_run_position(offset);
         },
-        NodeContent::EscapedCharacter((_, pos)) => {
+        MathItem::EscapedCharacter((_, pos)) | MathItem::Entity((_, pos)) => {
             return pos.clone();
         },
-        NodeContent::Child(_) => panic!("This shouldn't have been called.")
+        MathItem::Child(_) => panic!("This shouldn't have been called.")
     };
 }
 
 
 // OPTI: that's O(n²) because of chars().nth(). Also a lot of vec and Alias allocations
 /// Returns the longest possible alias at specified position, returns None if no alias found 
-fn check_for_alias<'a>(node_content: &Vec<NodeContent>, index: usize, context: &'a Context) -> Option<Alias<'a>> {
+fn check_for_alias<'a>(content: &Vec<MathItem>, index: usize, context: &'a Context) -> Option<Alias<'a>> {
     let mut all_aliases: Vec<Alias> = Vec::new();
-    for (_name, tag) in &context.custom_tags {
-        if tag.alias != None {
+    for tag in context.custom_tags.values() {
+        if let Some(alias) = &tag.alias {
             all_aliases.push(Alias {
-                alias: &tag.alias.as_ref().unwrap(),
+                alias,
                 tag_name: &tag.content.name,
                 is_infix: tag.infix_alias,
             });
@@ -727,18 +789,16 @@ fn check_for_alias<'a>(node_content: &Vec<NodeContent>, index: usize, context: &
 
             match opt_char {
                 Some(alias_char) => {
-                    let node_char;
-
-                    if index + pos >= node_content.len() {
-                        node_char = '\0';
+                    let node_char = if index + pos >= content.len() {
+                        '\0'
                     }
                     else {
-                        node_char = match node_content[index + pos] {
-                            NodeContent::Character((c, _)) => c,
-                            NodeContent::EscapedCharacter(_) => '\0',
-                            NodeContent::Child(_) => '\0',
-                        };
-                    }
+                        match content[index + pos] {
+                            MathItem::Character((c, _)) => c,
+                            MathItem::EscapedCharacter(_) | MathItem::Entity(_) => '\0',
+                            MathItem::Child(_) => '\0',
+                        }
+                    };
         
                     let still_ok = alias_char == node_char;
         
@@ -753,7 +813,7 @@ fn check_for_alias<'a>(node_content: &Vec<NodeContent>, index: usize, context: &
 
         }
 
-        if new_vec.len() == 0 { // No more potential matches
+        if new_vec.is_empty() { // No more potential matches
             break;
         }
 
@@ -761,20 +821,30 @@ fn check_for_alias<'a>(node_content: &Vec<NodeContent>, index: usize, context: &
         pos += 1;
     }
 
-    return match res {
-        Some(i) => Some(all_aliases.swap_remove(i)),
-        None => None,
-    };
+    return res.map(|i| all_aliases.swap_remove(i));
 }
 
 
 // Helper for parse_math_part
-fn parse_math_subgroup(node: &mut Node, children: &mut Vec<PotentialChild>, index: &mut usize, context: &Context, how_to_stop: MathStopType) 
+// If there is an error inside braces, parentheses or brackets, it is remembered with `add_error`, and parsing continues after the closing one
+fn parse_math_subgroup(content: &Vec<MathItem>, children: &mut Vec<PotentialChild>, index: &mut usize, context: &Context, how_to_stop: MathStopType) 
     -> Result<(Node, MathParseInfo), ParseError> {
     let start_pos = *index;
-    let (start_position, _) = get_file_pos_of_node_content(node, children, *index);
+    let (start_position, _) = get_file_pos_of_node_content(content, children, *index);
 
-    let (partial_child, info) = parse_math_part(node, children, index, context, how_to_stop)?;
+    let delimiters = get_group_delimiters(&how_to_stop);
+
+    let (partial_child, info) = match parse_math_part(content, children, index, context, how_to_stop) {
+        Ok(res) => res,
+        Err(err) => match delimiters {
+            Some((opening, closing)) => {
+                super::add_error(context, err);
+                skip_to_group_end(content, index, opening, closing);
+                (PartialNode { children: vec![], content: vec![] }, MathParseInfo { got_nothing: false })
+            },
+            None => return Err(err),
+        },
+    };
 
     let res = Node {
         name: String::from("div"),
@@ -793,17 +863,52 @@ fn parse_math_subgroup(node: &mut Node, children: &mut Vec<PotentialChild>, inde
 }
 
 
+/// The characters that open and close a group, if it has some
+fn get_group_delimiters(how_to_stop: &MathStopType) -> Option<(char, char)> {
+    return match how_to_stop {
+        MathStopType::Brace => Some(('{', '}')),
+        MathStopType::Parenthesis => Some(('(', ')')),
+        MathStopType::SquareBracket => Some(('[', ']')),
+        MathStopType::MathEnd | MathStopType::OneThing => None,
+    };
+}
+
+
+/// Moves `index` after the character that closes the current group, or to the end of the node
+fn skip_to_group_end(content: &Vec<MathItem>, index: &mut usize, opening: char, closing: char) {
+    let mut depth = 0;
+
+    while *index < content.len() {
+        if let MathItem::Character((c, _)) = content[*index] {
+            if c == opening {
+                depth += 1;
+            }
+            else if c == closing {
+                if depth == 0 {
+                    *index += 1;
+                    return;
+                }
+
+                depth -= 1;
+            }
+        }
+
+        *index += 1;
+    }
+}
+
+
 /// Converts a char to greek, returns None if non-alphabetical, Q or W
 fn letter_to_greek(c: char) -> Option<char> {
     let ascii_code = c as u8;
 
     if !c.is_ascii() { return None; }
 
-    if 'A' <= c && c <= 'Z' && c != 'Q' && c != 'W' {
-        return Some("ΑΒΨΔΕΦΓΗΙΞΚΛΜΝΟΠ ΡΣΤΘΩ ΧΥΖ".chars().nth((ascii_code - ('A' as u8)) as usize).expect("Uuh?"));
+    if c.is_ascii_uppercase() && c != 'Q' && c != 'W' {
+        return Some("ΑΒΨΔΕΦΓΗΙΞΚΛΜΝΟΠ ΡΣΤΘΩ ΧΥΖ".chars().nth((ascii_code - b'A') as usize).expect("Uuh?"));
     }
-    else if 'a' <= c && c <= 'z' && c != 'q' && c != 'w' {
-        return Some("αβψδεφγηιξκλμνοπ ρστθω χυζ".chars().nth((ascii_code - ('a' as u8)) as usize).expect("Uuh?"));
+    else if c.is_ascii_lowercase() && c != 'q' && c != 'w' {
+        return Some("αβψδεφγηιξκλμνοπ ρστθω χυζ".chars().nth((ascii_code - b'a') as usize).expect("Uuh?"));
     }
 
     return None;
@@ -812,8 +917,8 @@ fn letter_to_greek(c: char) -> Option<char> {
 
 /// Helper for parse_math_part
 /// Reports errors due to unexpected braces and similar 
-fn report_stop_error(node: &Node, expected: MathStopType, found: &MathToken, index: usize) -> ParseError {
-    let position = get_file_pos_of_node_char(node, index - 1);
+fn report_stop_error(content: &Vec<MathItem>, expected: MathStopType, found: &MathToken, index: usize) -> ParseError {
+    let position = get_file_pos_of_node_char(content, index - 1);
 
     let found_str = match found {
         MathToken::OpeningBrace => "{",
@@ -832,27 +937,27 @@ fn report_stop_error(node: &Node, expected: MathStopType, found: &MathToken, ind
     match expected {
         MathStopType::MathEnd => ParseError {
             message: format!("Unmatched \"{}\".", found_str),
-            position: position,
+            position,
             length: 1,
         },
         MathStopType::OneThing => ParseError {
             message: format!("Expected something before \"{}\".", found_str),
-            position: position,
+            position,
             length: 1,
         },
         MathStopType::Brace => ParseError {
             message: format!("Unmatched \"{}\". Opened with \"{{\", but closed with \"{}\".", found_str, found_str),
-            position: position,
+            position,
             length: 1,
         },
         MathStopType::Parenthesis => ParseError {
             message: format!("Unmatched \"{}\". Opened with \"(\", but closed with \"{}\".", found_str, found_str),
-            position: position,
+            position,
             length: 1,
         },
         MathStopType::SquareBracket => ParseError {
             message: format!("Unmatched \"{}\". Opened with \"[\", but closed with \"{}\".", found_str, found_str),
-            position: position,
+            position,
             length: 1,
         },
     }
@@ -864,19 +969,50 @@ fn compare_math_token_and_math_stop(token: &MathToken, stop: &MathStopType) -> b
     match stop {
         MathStopType::MathEnd => false,
         MathStopType::OneThing => false,
-        MathStopType::Brace => match token {
-            MathToken::ClosingBrace => true,
-            MathToken::ClosingVisibleBrace(_) => true,
-            _ => false
-        },
-        MathStopType::Parenthesis => match token {
-            MathToken::ClosingParenthesis(_) => true,
-            _ => false
-        },
-        MathStopType::SquareBracket => match token {
-            MathToken::ClosingSquareBracket(_) => true,
-            _ => false
-        },
+        MathStopType::Brace => matches!(token, MathToken::ClosingBrace | MathToken::ClosingVisibleBrace(_)),
+        MathStopType::Parenthesis => matches!(token, MathToken::ClosingParenthesis(_)),
+        MathStopType::SquareBracket => matches!(token, MathToken::ClosingSquareBracket(_)),
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::rc::Rc;
+    use super::*;
+    use crate::test_util::{parse_snippet, with_context};
+
+    /// Parses the math of the tag, and returns the positions (line, column) of the errors that start with `message`
+    fn get_error_positions(source: &str, message: &str) -> Vec<(usize, usize)> {
+        let errors = with_context(|context| {
+            let mut node = parse_snippet(source, context);
+            let res = parse_all_math(&mut node, false, context);
+            return crate::parser::collect_errors(res, context).expect_err("should fail");
+        });
+
+        return errors.iter()
+            .filter(|e| e.message.starts_with(message))
+            .map(|e| (e.position.line, e.position.line_character))
+            .collect();
+    }
+
+    #[test]
+    fn characters_of_runs_have_their_own_position() {
+        let start = FilePosition { file_path: Rc::new(PathBuf::from("doc.cow")), absolute_position: 40, line: 3, line_character: 5 };
+        let content = vec![NodeContent::Text((String::from("ab\ncd"), start))];
+        let items = get_math_items(&content);
+
+        let positions: Vec<(usize, usize, usize)> = (0..items.len())
+            .map(|i| get_file_pos_of_node_char(&items, i))
+            .map(|pos| (pos.absolute_position, pos.line, pos.line_character))
+            .collect();
+        assert_eq!(positions, vec![(40, 3, 5), (41, 3, 6), (42, 3, 7), (43, 4, 0), (44, 4, 1)]);
+    }
+
+    #[test]
+    fn errors_in_math_are_at_their_character() {
+        assert_eq!(get_error_positions("<p>$a + b\n  c !{ d$</p>", "You used \"!{\""), vec![(1, 4)]);
+        assert_eq!(get_error_positions("<p>$x = 1,\n y = ?nope$</p>", "Unknown math operator name"), vec![(1, 5)]);
+    }
+}
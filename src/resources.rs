@@ -0,0 +1,327 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use include_dir::{include_dir, Dir};
+
+use crate::{log, Failure};
+
+// Finds the folder that contains the resources of cowtchoox (default/, js/ and fonts/)
+// They are also embedded in the executable, and extracted to a cache folder if they can't be found elsewhere
+// Also finds cowx libraries in the search path
+
+
+/// Environment variable that can point to the resources folder
+pub const HOME_VARIABLE: &str = "COWTCHOOX_HOME";
+
+/// Environment variable containing folders in which cowx libraries are searched, separated like PATH
+pub const PATH_VARIABLE: &str = "COWTCHOOX_PATH";
+
+/// The resources folders, with their content embedded at compile time
+static EMBEDDED_FOLDERS: [(&str, Dir); 3] = [
+    ("default", include_dir!("$CARGO_MANIFEST_DIR/default")),
+    ("js", include_dir!("$CARGO_MANIFEST_DIR/js")),
+    ("fonts", include_dir!("$CARGO_MANIFEST_DIR/fonts")),
+];
+
+
+/// Finds the resources folder. The first of these that contains `default/default.cowx` is used:
+/// * the folder given with `--resources-dir`
+/// * the folder in the `COWTCHOOX_HOME` environment variable
+/// * the folder of the executable, after following symlinks
+/// * `../share/cowtchoox` from the folder of the executable, where package managers put it
+/// * the folder of the sources cowtchoox was built from, so that `cargo run` works from anywhere
+/// * a cache folder, into which the resources embedded in the executable are extracted if needed
+///
+/// If the folder is given explicitly (argument or variable) and is not valid, the other ones are not tried.
+///
+/// # Arguments
+/// * `resources_dir`: the value of `--resources-dir`
+///
+pub fn find_resources_dir(resources_dir: Option<&str>) -> Result<PathBuf, Failure> {
+    let home_dir = env::var_os(HOME_VARIABLE).map(PathBuf::from);
+    return search_resources_dir(resources_dir.map(PathBuf::from), home_dir, &get_candidate_dirs(), &get_cache_dir());
+}
+
+
+/// Does the job of `find_resources_dir`, with the folders that depend on the environment given as arguments
+fn search_resources_dir(resources_dir: Option<PathBuf>, home_dir: Option<PathBuf>, candidates: &Vec<PathBuf>, cache_dir: &Path) -> Result<PathBuf, Failure> {
+    if let Some(dir) = resources_dir {
+        return check_explicit_dir(dir, "--resources-dir");
+    }
+
+    if let Some(dir) = home_dir {
+        return check_explicit_dir(dir, HOME_VARIABLE);
+    }
+
+    for candidate in candidates {
+        if is_resources_dir(candidate) {
+            return Ok(candidate.clone());
+        }
+    }
+
+    let err = match extract_embedded_resources(cache_dir) {
+        Ok(()) => return Ok(cache_dir.to_path_buf()),
+        Err(err) => err,
+    };
+
+    let mut notes: Vec<String> = candidates.iter().map(|candidate| format!("looked in {}", candidate.display())).collect();
+    notes.push(format!("failed to extract them to {}: {}", cache_dir.display(), err));
+    notes.push(format!("use --resources-dir or set the {} environment variable to specify their location", HOME_VARIABLE));
+    log::error_with_notes("Could not find the resources of cowtchoox (default/, js/ and fonts/).", notes);
+
+    return Err(Failure::MissingInput);
+}
+
+
+/// Copies the default/, js/ and fonts/ folders of `resources_dir` into `destination`. Used for slides, that need the resources next to the HTML file
+pub fn copy_resources(resources_dir: &Path, destination: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(destination)?;
+
+    for (name, _) in &EMBEDDED_FOLDERS {
+        copy_dir::copy_dir(resources_dir.join(name), destination.join(name))?;
+    }
+
+    return Ok(());
+}
+
+
+/// Writes the embedded resources in `destination`, if they are not already there
+fn extract_embedded_resources(destination: &Path) -> std::io::Result<()> {
+    if is_resources_dir(destination) {
+        return Ok(());
+    }
+
+    // Extract in a temporary folder first, so that an other instance of the program never uses half-written files
+    let parent = destination.parent().expect("Uuh?");
+    let temp_dir = parent.join(format!("extracting-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&temp_dir);
+
+    for (name, dir) in &EMBEDDED_FOLDERS {
+        let folder = temp_dir.join(name);
+        fs::create_dir_all(&folder)?;
+        dir.extract(&folder)?;
+    }
+
+    match fs::rename(&temp_dir, destination) {
+        Ok(()) => return Ok(()),
+        Err(err) => {
+            let _ = fs::remove_dir_all(&temp_dir);
+
+            if is_resources_dir(destination) { // An other instance did it first
+                return Ok(());
+            }
+            else {
+                return Err(err);
+            }
+        },
+    }
+}
+
+
+/// The folder in which the embedded resources are extracted. It depends on their content, so that different versions of cowtchoox don't mix their files
+fn get_cache_dir() -> PathBuf {
+    let base = env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("LOCALAPPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+
+    let mut hasher = DefaultHasher::new();
+    for (name, dir) in &EMBEDDED_FOLDERS {
+        name.hash(&mut hasher);
+        hash_dir(dir, &mut hasher);
+    }
+
+    return base.join("cowtchoox").join(format!("resources-{}-{:016x}", env!("CARGO_PKG_VERSION"), hasher.finish()));
+}
+
+
+fn hash_dir(dir: &Dir, hasher: &mut DefaultHasher) {
+    for file in dir.files() {
+        file.path().hash(hasher);
+        file.contents().hash(hasher);
+    }
+
+    for sub_dir in dir.dirs() {
+        hash_dir(sub_dir, hasher);
+    }
+}
+
+
+/// The folders that are tried when no folder is given explicitly, in order
+fn get_candidate_dirs() -> Vec<PathBuf> {
+    let mut res = Vec::new();
+
+    if let Ok(exe_path) = env::current_exe() {
+        let exe_path = exe_path.canonicalize().unwrap_or(exe_path);
+
+        if let Some(exe_dir) = exe_path.parent() {
+            res.push(exe_dir.to_path_buf());
+
+            if let Some(prefix) = exe_dir.parent() {
+                res.push(prefix.join("share").join("cowtchoox"));
+            }
+        }
+    }
+
+    // Only for development builds, so that "cargo run" uses the files of the repository. A release executable must not depend on the machine it was built on
+    #[cfg(debug_assertions)]
+    res.push(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+
+    return res;
+}
+
+
+/// Makes the path absolute and reports an error if the folder isn't valid
+///
+/// # Arguments
+/// * `origin`: where the path comes from, used in the error message
+///
+fn check_explicit_dir(dir: PathBuf, origin: &str) -> Result<PathBuf, Failure> {
+    let dir = env::current_dir().expect("Failed to get working dir").join(dir); // Paths given to the browser must be absolute
+
+    if is_resources_dir(&dir) {
+        return Ok(dir);
+    }
+    else {
+        log::error(&format!("The resources folder given by {} ({}) does not contain default/default.cowx.", origin, dir.display()));
+        return Err(Failure::MissingInput);
+    }
+}
+
+
+fn is_resources_dir(dir: &Path) -> bool {
+    return dir.join("default").join("default.cowx").is_file();
+}
+
+
+/// Finds a cowx library. A relative path is first looked for from `base_dir`, then in each folder of `COWTCHOOX_PATH`.
+/// If several files match, the first one is used and a warning lists them.
+/// If none matches, the path from `base_dir` is returned, and the error will be reported when reading it.
+pub fn find_cowx_file(path: &Path, base_dir: &Path) -> PathBuf {
+    let search_dirs = env::var_os(PATH_VARIABLE).map(|search_path| env::split_paths(&search_path).collect()).unwrap_or_default();
+    return search_cowx_file(path, base_dir, &search_dirs);
+}
+
+
+/// Does the job of `find_cowx_file`, with the folders of the search path given as arguments
+fn search_cowx_file(path: &Path, base_dir: &Path, search_dirs: &Vec<PathBuf>) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+
+    let mut candidates = vec![base_dir.join(path)];
+    for dir in search_dirs {
+        candidates.push(dir.join(path));
+    }
+
+    let found: Vec<&PathBuf> = candidates.iter().filter(|candidate| candidate.is_file()).collect();
+
+    match found.as_slice() {
+        [] => return candidates.swap_remove(0),
+        [file] => return file.to_path_buf(),
+        [chosen, others @ ..] => {
+            log::warning_with_notes(
+                &format!("Several cowx files are named \"{}\", using {}.", path.display(), chosen.display()),
+                others.iter().map(|other| format!("also found {}", other.display())).collect()
+            );
+
+            return chosen.to_path_buf();
+        },
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::write_files;
+
+    #[test]
+    fn explicit_folders_come_first() {
+        let dir = write_files(&[("a/default/default.cowx", ""), ("b/default/default.cowx", ""), ("c/default/default.cowx", "")]);
+        let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+        let search = |resources_dir: Option<&PathBuf>, home_dir: Option<&PathBuf>| {
+            log::capture(|| search_resources_dir(resources_dir.cloned(), home_dir.cloned(), &vec![c.clone()], &dir.join("cache")))
+        };
+
+        assert_eq!(search(Some(&a), Some(&b)).0, Ok(a.clone()));
+        assert_eq!(search(None, Some(&b)).0, Ok(b.clone()));
+        assert_eq!(search(None, None).0, Ok(c.clone()));
+
+        // An invalid explicit folder is an error, the next ones are not tried
+        let (res, diagnostics) = search(Some(&dir.join("invalid")), Some(&b));
+        assert_eq!(res, Err(Failure::MissingInput));
+        assert!(diagnostics[0].message.contains("--resources-dir"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn candidates_are_tried_in_order_and_reported() {
+        let dir = write_files(&[("a/default/default.cowx", ""), ("b/default/default.cowx", ""), ("file", "")]);
+        let candidates = vec![dir.join("invalid"), dir.join("b"), dir.join("a")];
+
+        let res = search_resources_dir(None, None, &candidates, &dir.join("cache"));
+        assert_eq!(res, Ok(dir.join("b")));
+
+        // The resources can't be extracted in a folder inside a file
+        let (res, diagnostics) = log::capture(|| search_resources_dir(None, None, &candidates[..1].to_vec(), &dir.join("file/cache")));
+        assert_eq!(res, Err(Failure::MissingInput));
+        assert!(diagnostics[0].notes[0].ends_with(&dir.join("invalid").display().to_string()));
+        assert!(diagnostics[0].notes.iter().any(|note| note.contains(HOME_VARIABLE)));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn embedded_resources_are_extracted_once() {
+        let dir = write_files(&[]);
+        let cache_dir = dir.join("cache").join("resources");
+
+        assert_eq!(search_resources_dir(None, None, &Vec::new(), &cache_dir), Ok(cache_dir.clone()));
+        for name in ["default/default.cowx", "js", "fonts"] {
+            let original = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(name);
+            let extracted = cache_dir.join(name);
+            assert!(extracted.exists(), "{}", name);
+
+            if original.is_file() {
+                assert_eq!(fs::read(extracted).unwrap(), fs::read(original).unwrap());
+            }
+        }
+
+        // Already extracted, so nothing is written again
+        fs::write(cache_dir.join("default").join("default.cowx"), "changed").unwrap();
+        assert!(extract_embedded_resources(&cache_dir).is_ok());
+        assert_eq!(fs::read_to_string(cache_dir.join("default").join("default.cowx")).unwrap(), "changed");
+        assert_eq!(fs::read_dir(dir.join("cache")).unwrap().count(), 1); // No temporary folder left
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn ambiguous_cowx_names_use_the_first_file_with_a_warning() {
+        let dir = write_files(&[("doc/tables.cowx", ""), ("lib1/tables.cowx", ""), ("lib2/tables.cowx", ""), ("lib2/plots.cowx", "")]);
+        let search_dirs = vec![dir.join("lib1"), dir.join("lib2")];
+        let search = |name: &str| log::capture(|| search_cowx_file(Path::new(name), &dir.join("doc"), &search_dirs));
+
+        let (path, diagnostics) = search("tables.cowx");
+        assert_eq!(path, dir.join("doc/tables.cowx"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].level, log::LogLevel::Warning);
+        assert!(diagnostics[0].message.contains(&dir.join("doc/tables.cowx").display().to_string()));
+        assert_eq!(diagnostics[0].notes.len(), 2);
+
+        let (path, diagnostics) = search("plots.cowx");
+        assert_eq!(path, dir.join("lib2/plots.cowx"));
+        assert!(diagnostics.is_empty());
+
+        // Not found: the error is reported when reading the file from the folder of the document
+        assert_eq!(search("missing.cowx").0, dir.join("doc/missing.cowx"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
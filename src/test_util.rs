@@ -0,0 +1,123 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::log::LogLevel;
+use crate::parser::{self, Node, TagSymbol};
+use crate::{compile, config, Args, CompileOptions, Context, Diagnostic};
+
+// Helpers for the unit tests
+
+
+/// Writes the files in a new temporary folder, and compiles the first one without creating a PDF
+///
+/// # Arguments
+/// * `files`: the paths of the files in the folder, and their content
+///
+/// # Returns
+/// * The HTML, if the compilation succeeded
+/// * All the warnings and errors
+///
+pub fn compile_files(files: &[(&str, &str)]) -> (Option<String>, Vec<Diagnostic>) {
+    let dir = write_files(files);
+
+    let options = CompileOptions {
+        base_dir: dir.clone(),
+        file_path: PathBuf::from(files[0].0),
+        resources_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        cowx_files: Vec::new(),
+        create_pdf: false,
+        variables: HashMap::new(),
+        flags: Vec::new(),
+    };
+
+    let res = compile(files[0].1, &options);
+    let _ = fs::remove_dir_all(&dir);
+
+    match res {
+        Ok(document) => return (Some(document.html), document.diagnostics),
+        Err(diagnostics) => return (None, diagnostics),
+    }
+}
+
+
+/// Writes the files in a new temporary folder, and returns its path. The caller removes the folder
+///
+/// # Arguments
+/// * `files`: the paths of the files in the folder, and their content
+///
+pub fn write_files(files: &[(&str, &str)]) -> PathBuf {
+    static DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("cowtchoox-test-{}-{}", std::process::id(), DIR_COUNTER.fetch_add(1, Ordering::Relaxed)));
+
+    for (name, content) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().expect("Uuh?")).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    return dir;
+}
+
+
+/// The messages of the diagnostics of that level
+pub fn get_messages(diagnostics: &Vec<Diagnostic>, level: LogLevel) -> Vec<&str> {
+    return diagnostics.iter().filter(|d| d.level == level).map(|d| d.message.as_str()).collect();
+}
+
+
+/// Calls `f` with the context of a document named doc.cow, with no custom tags nor flags
+pub fn with_context<T>(f: impl FnOnce(&mut Context) -> T) -> T {
+    let args = Args {
+        headful: false,
+        keep_alive: false,
+        filepath: String::from("doc.cow"),
+        no_pdf: true,
+        cowx_files: Vec::new(),
+        watch: false,
+        output: None,
+        html_output: None,
+        warnings_as_errors: false,
+        resources_dir: None,
+        emit_html: false,
+        variables: HashMap::new(),
+        flags: Vec::new(),
+    };
+
+    let mut context = Context {
+        args: &args,
+        custom_tags: HashMap::new(),
+        ignore_aliases: false,
+        default_dir: PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+        main_file_path: PathBuf::from("doc.cow"),
+        dependencies: RefCell::new(Vec::new()),
+        pdf_output_path: PathBuf::new(),
+        html_output_path: PathBuf::new(),
+        config: config::Config::default(),
+        flags: HashMap::new(),
+        errors: RefCell::new(Vec::new()),
+        unknown_flags_reported: RefCell::new(HashSet::new()),
+    };
+
+    return f(&mut context);
+}
+
+
+/// Parses the custom tags of a cowx file named tags.cowx, and adds them to the context
+pub fn add_custom_tags(cowx: &str, context: &mut Context) {
+    let mut pos = parser::get_start_of_file_position(PathBuf::from("tags.cowx"));
+    let custom_tags = std::mem::take(&mut context.custom_tags);
+    let res = parser::custom::parse_custom_tags(&cowx.chars().collect(), &mut pos, custom_tags, context.args, false, &context.default_dir, &PathBuf::from("tags.cowx"));
+
+    context.custom_tags = res.expect("should be valid");
+}
+
+
+/// Parses one tag of doc.cow, or one custom tag definition, without the steps that come after parsing in `parser::parse_file`
+pub fn parse_snippet(source: &str, context: &Context) -> Node {
+    let mut pos = parser::get_start_of_file_position(PathBuf::from("doc.cow"));
+    let symbols = TagSymbol::NOTHING | TagSymbol::EXCLAMATION_MARK | TagSymbol::QUESTION_MARK;
+    return parser::parse_tag(&source.chars().collect(), &mut pos, symbols, false, context).expect("should parse");
+}
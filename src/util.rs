@@ -14,10 +14,10 @@ pub struct FilePosition {
 }
 
 
-pub fn get_browser_path_string(path: PathBuf, with_file_prefix: bool) -> String {
+pub fn get_browser_path_string(path: PathBuf, _with_file_prefix: bool) -> String {
     let mut path_str = path.display().to_string();
     path_str = path_str.replace('\\', "/").replace("//?/", ""); // HACK: sometimes "//?/" appears, don't know why
 
-    let file_prefix = if with_file_prefix { "" } else { "" };
-    return format!("{}{}", file_prefix, path_str);
+    // The "file://" prefix is added by the callers
+    return path_str;
 }
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::{log, Args};
+
+// Watch mode: recompiles the document each time one of the files it uses changes
+
+
+/// Time between two checks of the files
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Time to wait after a change is detected, so that the editor has finished writing the files
+const SETTLE_DELAY: Duration = Duration::from_millis(100);
+
+
+/// Compiles the document, then recompiles it each time a dependency changes. Never returns.
+/// The same browser is used for all compilations.
+pub fn watch(args: &Args, resources_dir: &PathBuf) -> ! {
+    let mut browser = None;
+    let mut dependencies = vec![PathBuf::from(&args.filepath)]; // Only the document is known before the first compilation
+
+    loop {
+        // Read the times before compiling, so that the files saved during the compilation are compiled again
+        let times_before_compilation: HashMap<PathBuf, Option<SystemTime>> = dependencies.iter()
+            .map(|path| (path.clone(), get_modification_time(path)))
+            .collect();
+
+        (dependencies, _) = crate::compile(args, resources_dir, &mut browser);
+
+        let initial_times = dependencies.iter()
+            .map(|path| match times_before_compilation.get(path) {
+                Some(time) => *time,
+                None => get_modification_time(path), // Found during this compilation
+            })
+            .collect();
+
+        log::log(&format!("Watching {} files for changes. Press Ctrl+C to stop.", dependencies.len()));
+        wait_for_change(&dependencies, &initial_times);
+        thread::sleep(SETTLE_DELAY);
+
+        log::log("Change detected, recompiling...");
+    }
+}
+
+
+/// Blocks until the modification time of one of the files is not the one in `initial_times`: it has been modified, created or deleted
+fn wait_for_change(paths: &Vec<PathBuf>, initial_times: &Vec<Option<SystemTime>>) {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+
+        for (path, initial_time) in paths.iter().zip(initial_times) {
+            if get_modification_time(path) != *initial_time {
+                return;
+            }
+        }
+    }
+}
+
+
+/// Returns None if the file does not exist or can't be read
+fn get_modification_time(path: &PathBuf) -> Option<SystemTime> {
+    return fs::metadata(path).and_then(|meta| meta.modified()).ok();
+}
@@ -1,98 +1,113 @@
 
 use std::fs;
-use std::path::PathBuf;
 
 use crate::doc_options::DocumentPath;
 use crate::log;
-use crate::Context;
+use crate::{Context, Failure};
 use crate::parser::{Node, NodeContent, ParseError};
-use crate::parser::custom;
+use crate::util::FilePosition;
+use crate::parser::{custom, conditions};
 use crate::doc_options;
+use crate::highlight;
 
 // Transform the struct back to raw HTML
 // NOTE: all text will be wrapped in <text> tags
 
 // Get the entire text of the document, ready for being displayed
-pub fn get_file_text(document: Node, context: &mut Context, options: &doc_options::DocOptions) -> Result<String, ()> {
+pub fn get_file_text(document: Node, context: &mut Context, options: &doc_options::DocOptions) -> Result<String, Failure> {
     let mut res = String::new();
 
+    let body = get_finished_body(document, context, options)?;
+
+    res.push_str("<html>"); // Quirks is better!
+
+    res.push_str(&write_head(options, context));
+
+    // Write the body text
+    res.push_str(&get_node_html(&body, false));
+
+    res.push_str("</html>");
+
+    return Ok(res);
+}
+
+
+/// Loads the cowx files of the head, then instantiates custom tags, parses math, and inserts header and footer.
+/// Returns the body, ready to be converted to HTML
+pub fn get_finished_body(document: Node, context: &mut Context, options: &doc_options::DocOptions) -> Result<Node, Failure> {
+    // Flags declared in the head are not set, unless the head or the command line sets them
+    for (flag, is_set) in &options.flags {
+        context.flags.insert(flag.clone(), *is_set);
+    }
+    for flag in &context.args.flags {
+        context.flags.insert(flag.clone(), true);
+    }
+
     // Look for additional cowx files listed in head
     for cowx_file in &options.cowx_files {
-        let path = cowx_file.get_full_path(context);
+        let path = cowx_file.get_cowx_path(context);
+        context.dependencies.borrow_mut().push(path.clone());
+
         let content = match fs::read_to_string(path.clone()) {
             Ok(content) => content,
             Err(err) => {
                 log::error(
                     &format!(
-                        "Could not read cowx file \"{}\" specified in document head. ({}) Make sure the path is relative to the compiled file.", 
-                        path.display(), err
+                        "Could not read cowx file \"{}\" specified in document head. ({}) Make sure the path is relative to the compiled file, or to a folder of {}.", 
+                        path.display(), err, crate::resources::PATH_VARIABLE
                     )
                 );
-                return Err(());
+                return Err(Failure::MissingInput);
             },
         };
 
         // Parse the file!
         match custom::parse_custom_tags(
             &content.chars().collect(), 
-            &mut crate::parser::get_start_of_file_position(PathBuf::from(cowx_file.get_full_path(context))), 
-            std::mem::replace(&mut context.custom_tags, std::collections::HashMap::new()),
-            &context.args, 
+            &mut crate::parser::get_start_of_file_position(path.clone()), 
+            std::mem::take(&mut context.custom_tags),
+            context.args, 
             false,
             &context.default_dir,
             &path
         ) {
             Ok(res) => context.custom_tags = res,
-            Err(err) => {
-                log::error_position(&err.message, &err.position, err.length);
+            Err(errors) => {
+                crate::parser::log_errors(&errors);
+                return Err(Failure::Parse);
             },
         }
     } 
 
-    let mut finished_document = parse_math_and_replace_tags(document, &context)?;
+    let finished_document = parse_math_and_replace_tags(document, context)?;
 
     // Get the body from the document
-    let mut body = match try_get_children_with_name(&mut finished_document, "body") {
-        Ok(res) => res,
-        Err(()) => {
+    let mut body = match finished_document.children.into_iter().find(|child| child.name == "body") {
+        Some(res) => res,
+        None => {
             log::error("The document has no body");
-            return Err(());
+            return Err(Failure::Parse);
         }
     };
 
     // Parse the header and footer, if found add it as a child to the body
-    match &options.footer_file {
-        Some(file) => {
-            insert_footer_or_header(true, file, &mut body, context)?;
-        },
-        None => {},
+    if let Some(file) = &options.footer_file {
+        insert_footer_or_header(true, file, &mut body, context)?;
     };    
-    match &options.header_file {
-        Some(file) => {
-            insert_footer_or_header(false, file, &mut body, context)?;
-        },
-        None => {},
+    if let Some(file) = &options.header_file {
+        insert_footer_or_header(false, file, &mut body, context)?;
     };    
 
-    res.push_str("<html>"); // Quirks is better!
-
-    res.push_str(&write_head(&options, &context));
-
-    // Write the body text
-    res.push_str(&get_node_html(&body, false, &context));
-
-    res.push_str("</html>");
-
-    return Ok(res);
+    return Ok(body);
 }
 
 
 // Compiles and inserts the header or footer into the body. Helper for `get_file_text`
-fn insert_footer_or_header(is_footer: bool, path: &DocumentPath, body: &mut Node, context: &Context) -> Result<(), ()> {
+fn insert_footer_or_header(is_footer: bool, path: &DocumentPath, body: &mut Node, context: &Context) -> Result<(), Failure> {
     let file_res = std::fs::read_to_string(path.get_full_path(context));
     match file_res {
         Ok(string) => {
-            let parsed = crate::parser::parse_file(&PathBuf::from(path.get_full_path(context).clone()), &string.chars().collect(), context);
+            let parsed = crate::parser::parse_file(&path.get_full_path(context).clone(), &string.chars().collect(), context);
             match parsed {
                 Ok(mut node) => {
                     // Add the footer or header as a child
@@ -104,37 +119,51 @@ fn insert_footer_or_header(is_footer: bool, path: &DocumentPath, body: &mut Node
                 
                     return Ok(());
                 },
-                Err(err) => {
-                    log::error_position(&err.message, &err.position, err.length);
-                    return Err(());
+                Err(errors) => {
+                    crate::parser::log_errors(&errors);
+                    return Err(Failure::Parse);
                 },
             }
         },
         Err(err) => {
             let thing_type = if is_footer { "footer" } else { "header" };
             log::error(&format!("Failed to read the {} file: {} The path cowtchoox tries to reach is \"{}\"", thing_type, err, path.get_full_path(context).display()));
-            return Err(());
+            return Err(Failure::MissingInput);
         },
     }
 }
 
 
-fn parse_math_and_replace_tags(node: Node, context: &Context) -> Result<Node, ()> {
-    // Instantiate the custom tags used in the document
-    let mut with_custom_tags = match instantiate_all_custom_tags(node, false, context) {
-        Ok(node) => node,
+fn parse_math_and_replace_tags(mut node: Node, context: &Context) -> Result<Node, Failure> {
+    // Keep only the content of conditional blocks that are chosen
+    match conditions::evaluate_conditions(&mut node, context) {
+        Ok(()) => {},
         Err(err) => {
             log::error_position(&err.message, &err.position, err.length);
-            return Err(());
+            return Err(Failure::Parse);
+        },
+    }
+
+    // Split the content of math and of the tags that ask for it into rows and cells
+    crate::parser::split_all_ampersands(&mut node, false, context);
+
+    // Instantiate the custom tags used in the document
+    let res = instantiate_all_custom_tags(node, false, context);
+    let mut with_custom_tags = match crate::parser::collect_errors(res, context) {
+        Ok(node) => node,
+        Err(errors) => {
+            crate::parser::log_errors(&errors);
+            return Err(Failure::Parse);
         },
     };
 
     // Parse the math
-    match crate::parser::math::parse_all_math(&mut with_custom_tags, false, context) {
+    let res = crate::parser::math::parse_all_math(&mut with_custom_tags, false, context);
+    match crate::parser::collect_errors(res, context) {
         Ok(()) => {},
-        Err(err) => {
-            log::error_position(&err.message, &err.position, err.length);
-            return Err(());
+        Err(errors) => {
+            crate::parser::log_errors(&errors);
+            return Err(Failure::Parse);
         },
     };
 
@@ -152,8 +181,6 @@ pub fn write_head(options: &doc_options::DocOptions, context: &Context) -> Strin
     // Document title
     res.push_str(format!("<title>{}</title>", options.title).as_str());
 
-    // FIXME: should be like ~"path_to_exe/" when built, and ~"" when running with cargo
-    //        but too lazy to do that
     let default_resources_path = context.default_dir.to_str().expect("Failed to get resources dir string").to_string().replace("\\", "/");
 
     // Link JS script, so that it executes when the page loads
@@ -162,17 +189,18 @@ pub fn write_head(options: &doc_options::DocOptions, context: &Context) -> Strin
     
     // Link additional JS scripts
     for file_path in &options.js_files {
-        let path_str = crate::util::get_browser_path_string(file_path.get_full_path(context), !options.is_slides);
+        let path_str = crate::util::get_browser_path_string(file_path.get_link_path(context), !options.is_slides);
         res.push_str(&format!("<script defer=\"defer\" src=\"{}\"></script>", path_str));
     }
     
     // Link default CSS
     res.push_str(&format!("<link rel=\"stylesheet\" href=\"{}{}/default/util.css\"/>", file_prefix, default_resources_path));
     res.push_str(&format!("<link rel=\"stylesheet\" href=\"{}{}/default/default.css\"/>", file_prefix, default_resources_path));
+    res.push_str(&format!("<link rel=\"stylesheet\" href=\"{}{}/default/highlight.css\"/>", file_prefix, default_resources_path));
 
     // Link additional CSS
     for file_path in &options.css_files {
-        let path_str = crate::util::get_browser_path_string(file_path.get_full_path(context), !options.is_slides);
+        let path_str = crate::util::get_browser_path_string(file_path.get_link_path(context), !options.is_slides);
         res.push_str(&format!("<link rel=\"stylesheet\" href=\"{}\"/>", path_str));
     }
 
@@ -211,7 +239,7 @@ pub fn try_get_children_with_name<'a>(document: &'a mut Node, name: &str) -> Res
 ///
 /// # Arguments
 /// * `no_text_tags`: will not create <text> tags (for pre of svg)
-pub fn get_node_html(node: &Node, no_text_tags: bool, context: &Context) -> String {
+pub fn get_node_html(node: &Node, no_text_tags: bool) -> String {
     let mut res = String::from("<");
 
     res.push_str(&node.name);
@@ -232,6 +260,11 @@ pub fn get_node_html(node: &Node, no_text_tags: bool, context: &Context) -> Stri
     if node.auto_closing {
         res.push_str("/>");
     }
+    else if let Some(highlighted) = get_highlighted_code_html(node) {
+        res.push('>');
+        res.push_str(&highlighted);
+        res.push_str(&format!("</{}>", &node.name));
+    }
     else {
         res.push('>');
 
@@ -242,27 +275,38 @@ pub fn get_node_html(node: &Node, no_text_tags: bool, context: &Context) -> Stri
 
         for content in &node.content {
             match content {
-                crate::parser::NodeContent::Character((c, _)) | NodeContent::EscapedCharacter((c, _)) => {
+                crate::parser::NodeContent::Text((text, _)) => {
                     if !in_text {
                         in_text = true;
                     }
 
-                    // Escape characters
-                    if *c == '<' {
-                        current_text_tag.push_str("&lt;");
+                    for c in text.chars() {
+                        push_escaped_character(&mut current_text_tag, c);
                     }
-                    else if *c == '>' {
-                        current_text_tag.push_str("&gt;");
+                },
+                crate::parser::NodeContent::EscapedCharacter((c, _)) => {
+                    if !in_text {
+                        in_text = true;
                     }
-                    else if *c == '&' {
-                        current_text_tag.push_str("&amp;");
+
+                    push_escaped_character(&mut current_text_tag, *c);
+                },
+                crate::parser::NodeContent::Entity((entity, _)) => {
+                    if !in_text {
+                        in_text = true;
                     }
-                    else {
-                        current_text_tag.push(*c);
+
+                    current_text_tag.push_str(entity); // Already valid HTML
+                },
+                crate::parser::NodeContent::Ampersand((is_double, _)) => {
+                    if !in_text {
+                        in_text = true;
                     }
+
+                    current_text_tag.push_str(if *is_double { "&amp;&amp;" } else { "&amp;" });
                 },
                 crate::parser::NodeContent::Child(id) => {
-                    if in_text && current_text_tag.trim().len() != 0 {
+                    if in_text && !current_text_tag.trim().is_empty() {
                         if !no_text_tags {
                             inner_html.push_str("<text>");
                         }
@@ -282,13 +326,12 @@ pub fn get_node_html(node: &Node, no_text_tags: bool, context: &Context) -> Stri
                         || node.children[*id].name == "svg" 
                         || node.children[*id].name == "pre" 
                         || node.children[*id].name == "style" 
-                        || node.children[*id].name == "script", 
-                        context))
+                        || node.children[*id].name == "script"))
                 },
             }
         }
 
-        if in_text && current_text_tag.trim().len() != 0 {
+        if in_text && !current_text_tag.trim().is_empty() {
             if !no_text_tags {
                 inner_html.push_str("<text>");
             }
@@ -311,20 +354,69 @@ pub fn get_node_html(node: &Node, no_text_tags: bool, context: &Context) -> Stri
 }
 
 
-/// Looks for custom tags in document, then replaces them with their definition
+/// Returns the highlighted content of a code block that has a language, or None if it should be written normally
+fn get_highlighted_code_html(node: &Node) -> Option<String> {
+    if node.name != "pre" && node.name != "code" {
+        return None;
+    }
+
+    let language_name = highlight::get_language_name(node)?;
+
+    if !highlight::is_known_language(language_name) {
+        log::warning_position(&format!("Unknown language \"{}\", the code is not highlighted.", language_name), &node.start_position, 1);
+        return None;
+    }
+
+    if !node.children.is_empty() { // Written with <pre> or <code> tags, and contains other tags
+        return None;
+    }
+
+    let mut code = crate::parser::get_node_content_as_str(node);
+    if node.name == "pre" {
+        code = code.trim().to_string();
+    }
+
+    return highlight::get_highlighted_html(&code, language_name);
+}
+
+
+/// Adds a character of the text, escaped for HTML
+fn push_escaped_character(res: &mut String, c: char) {
+    if c == '<' {
+        res.push_str("&lt;");
+    }
+    else if c == '>' {
+        res.push_str("&gt;");
+    }
+    else if c == '&' {
+        res.push_str("&amp;");
+    }
+    else {
+        res.push(c);
+    }
+}
+
+
+/// Looks for custom tags in document, then replaces them with their definition.
+/// The errors of the children are added to the context, so that all the custom tags are checked. See `parser::collect_errors`
 pub fn instantiate_all_custom_tags(mut node: Node, only_children: bool, context: &Context) -> Result<Node, ParseError> {
     // Put children in an option array
-    let owned_children = std::mem::replace(&mut node.children, Vec::new());
-    let mut opt_children : Vec<_> = owned_children.into_iter().map(|c| Some(c)).collect();
+    let owned_children = std::mem::take(&mut node.children);
+    let mut opt_children : Vec<_> = owned_children.into_iter().map(Some).collect();
 
     for content in &node.content {
-        match content {
-            NodeContent::Child(id) => {
-                let child = std::mem::replace(&mut opt_children[*id], None).unwrap();
-                let changed = instantiate_all_custom_tags(child, false, context)?; // Instantiate tags inside children
-                opt_children[*id] = Some(changed);
-            },
-            _ => {},
+        if let NodeContent::Child(id) = content {
+            let child = opt_children[*id].take().unwrap();
+            let child_position = child.start_position.clone();
+
+            // Instantiate tags inside children
+            match instantiate_all_custom_tags(child, false, context) {
+                Ok(changed) => opt_children[*id] = Some(changed),
+                Err(err) => {
+                    crate::parser::add_error(context, err);
+                    opt_children[*id] = Some(get_placeholder_node(child_position));
+                },
+            }
         }
     }
 
@@ -359,14 +451,15 @@ pub fn instantiate_all_custom_tags(mut node: Node, only_children: bool, context:
             if chars.next().unwrap() == ':' {
                 match &attr.value {
                     Some(val) => {
-                        let mut val_pos = attr.value_position.clone().expect("The tag argument does not come from source file!");
+                        let val_pos = attr.value_position.clone().expect("The tag argument does not come from source file!");
 
                         // HACK: put a space after to prevent the parser from complaining it gets the end of the string
                         // [5 monts later]: Apparently one space isn't enough, so here is a second space
                         let mut padded_val = val.clone();
                         padded_val.push(' ');
                         padded_val.push(' ');
-                        let node = crate::parser::get_tag_from_raw_text(&padded_val, custom_tag.is_math, &mut val_pos, context)?;
+                        let mut node = crate::parser::get_tag_from_raw_text(&padded_val, custom_tag.is_math, &val_pos, context)?;
+                        crate::parser::split_all_ampersands(&mut node, false, context);
                         arguments.push((chars.collect(), node));
                     },
                     None => {
@@ -414,6 +507,7 @@ pub fn instantiate_all_custom_tags(mut node: Node, only_children: bool, context:
         // OPTI: this may be very slow, and can even crash if there is a loop in custom tags dependencies 
         // Instantiate custom tags inside the custom tags
         let mut tag_to_instantiate = (*custom_tag).clone();
+        conditions::evaluate_conditions(&mut tag_to_instantiate.content, context)?;
         tag_to_instantiate.content = instantiate_all_custom_tags(tag_to_instantiate.content, true, context)?;
 
         let mut actual_res = custom::instantiate_tag_with_named_parameters(&tag_to_instantiate, arguments, &start_position)?;
@@ -425,3 +519,67 @@ pub fn instantiate_all_custom_tags(mut node: Node, only_children: bool, context:
         return Ok(node);
     }
 }
+
+
+/// An empty node, that replaces a custom tag that could not be instantiated
+fn get_placeholder_node(position: FilePosition) -> Node {
+    return Node {
+        name: String::from("span"),
+        attributes: Vec::new(),
+        children: Vec::new(),
+        content: Vec::new(),
+        auto_closing: false,
+        is_math: false,
+        declaration_symbol: crate::parser::TagSymbol::NOTHING,
+        start_position: position.clone(),
+        start_inner_position: position,
+        source_length: 0,
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{add_custom_tags, parse_snippet, with_context};
+
+    /// Instantiates the custom tags of the body, and returns the messages of the errors
+    fn get_instantiation_errors(body: &str) -> Vec<String> {
+        return with_context(|context| {
+            add_custom_tags("<!box :title><:title/></box>", context);
+
+            let res = instantiate_all_custom_tags(parse_snippet(body, context), false, context);
+            let errors = crate::parser::collect_errors(res, context).expect_err("should fail");
+            return errors.into_iter().map(|e| e.message).collect();
+        });
+    }
+
+    #[test]
+    fn all_unknown_custom_tags_are_reported() {
+        let errors = get_instantiation_errors("<body><!unknowntag/> <p><!other/></p> <!unknowntag/></body>");
+
+        assert_eq!(errors.len(), 3);
+        assert!(errors.iter().all(|e| e.starts_with("Unknown custom tag")));
+        assert!(errors[1].contains("\"other\""));
+    }
+
+    #[test]
+    fn errors_in_arguments_do_not_hide_other_tags() {
+        let errors = get_instantiation_errors("<body><!box :title/> <!missing/></body>");
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("The argument :title has no value"));
+        assert!(errors[1].contains("Unknown custom tag \"missing\""));
+    }
+
+    #[test]
+    fn failed_tags_are_replaced_by_placeholders() {
+        with_context(|context| {
+            let node = instantiate_all_custom_tags(parse_snippet("<body>a <!missing/> b</body>", context), false, context).expect("errors are in the context");
+
+            assert_eq!(context.errors.borrow().len(), 1);
+            assert_eq!(node.children.len(), 1);
+            assert_eq!(node.children[0].name, "span");
+        });
+    }
+}